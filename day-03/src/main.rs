@@ -1,84 +1,464 @@
-use std::collections::BTreeMap;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{env, num::ParseIntError, str::FromStr};
 
 const INPUT: &str = include_str!("../input");
 
 fn main() {
+    // An optional tie policy overrides the manual's for every rating
+    let policy = match env::args().nth(1) {
+        Some(ties) => Policy::with_ties(ties.parse().unwrap()),
+        None => Policy::STANDARD,
+    };
+
+    let tied = tied_positions(INPUT).unwrap();
+    if !tied.is_empty() {
+        eprintln!("tied bit positions: {tied:?}");
+    }
+
     // Not 17640 -- real numbers are > 5 bits
-    println!("part1: {}", gamma_epsilon_product(INPUT));
-    println!("part2: {}", life_support_rating(INPUT));
+    println!("part1: {}", gamma_epsilon_product(INPUT, &policy));
+    println!("part2: {}", life_support_rating(INPUT, &policy));
+}
+
+fn gamma_epsilon_product(s: &str, policy: &Policy) -> u64 {
+    gamma_epsilon_product_inner(s, policy).unwrap()
 }
 
-fn gamma_epsilon_product(s: &str) -> u64 {
-    let mut map = BTreeMap::<_, i32>::new();
+fn gamma_epsilon_product_inner(s: &str, policy: &Policy) -> Result<u64> {
+    let report = Report::new(s)?;
+    let columns = report.column_counts();
+
+    let gamma = rate_columns(&columns, policy.gamma)?;
+    let epsilon = rate_columns(&columns, policy.epsilon)?;
+
+    Ok(epsilon * gamma)
+}
+
+fn rate_columns(columns: &[ColumnCount], criteria: BitCriteria) -> Result<u64> {
+    let mut value = 0u64;
+    let mut tied = vec![];
+
+    for (p, column) in columns.iter().enumerate() {
+        value <<= 1;
 
-    for l in clean_lines(s) {
-        for (p, c) in l.chars().enumerate() {
-            let delta = if c == '1' { 1 } else { -1 };
-            *map.entry(p).or_default() += delta;
+        match column.select(criteria) {
+            Some(Selection::Zero) => {}
+            Some(Selection::One) => value |= 1,
+            // A single value cannot keep both bits
+            Some(Selection::Both) | None => tied.push(p),
         }
     }
 
-    let mut gamma = 0u64;
-    let mut mask = 0;
+    ensure!(tied.is_empty(), TieSnafu { positions: tied });
+
+    Ok(value)
+}
+
+fn life_support_rating(s: &str, policy: &Policy) -> u64 {
+    life_support_rating_inner(s, policy).unwrap()
+}
+
+fn life_support_rating_inner(s: &str, policy: &Policy) -> Result<u64> {
+    let report = Report::new(s)?;
+    let trie = Trie::new(&report);
+
+    let oxygen = trie.rating(policy.oxygen)?;
+    let co2 = trie.rating(policy.co2)?;
+
+    Ok(oxygen * co2)
+}
+
+/// A binary trie over every line of the report, most significant bit
+/// first. Each node knows how many lines pass through it, so choosing
+/// a bit by some criteria is a constant-time look at two children.
+#[derive(Debug)]
+struct Trie {
+    nodes: Vec<TrieNode>,
+    width: usize,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: [Option<usize>; 2],
+    count: usize,
+}
+
+impl Trie {
+    const ROOT: usize = 0;
+
+    fn new(report: &Report) -> Self {
+        let mut nodes = vec![TrieNode::default()];
 
-    for (_p, c) in map {
-        assert_ne!(c, 0, "No majority");
+        for &row in &report.rows {
+            let mut node = Self::ROOT;
+            nodes[node].count += 1;
 
-        gamma <<= 1;
+            for depth in (0..report.width).rev() {
+                let bit = (row >> depth & 1) as usize;
+                let child = match nodes[node].children[bit] {
+                    Some(child) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children[bit] = Some(child);
+                        child
+                    }
+                };
 
-        if c > 0 {
-            gamma |= 1;
+                node = child;
+                nodes[node].count += 1;
+            }
         }
 
-        mask <<= 1;
-        mask |= 1;
+        Self {
+            nodes,
+            width: report.width,
+        }
     }
 
-    let omega = !gamma & mask;
-    omega * gamma
-}
+    /// Walks the trie, filtering by `criteria` one bit at a time. This
+    /// is O(width) unless ties keep both halves.
+    fn rating(&self, criteria: BitCriteria) -> Result<u64> {
+        let mut frontier = vec![(Self::ROOT, 0u64)];
+        let mut next = vec![];
+
+        for depth in 0..self.width {
+            let column = ColumnCount {
+                zeros: self.count_children(&frontier, 0),
+                ones: self.count_children(&frontier, 1),
+            };
+
+            // Never filter down to a bit that no remaining line has
+            let bits: &[usize] = if column.zeros == 0 {
+                &[1]
+            } else if column.ones == 0 {
+                &[0]
+            } else {
+                match column.select(criteria).context(TieSnafu {
+                    positions: vec![depth],
+                })? {
+                    Selection::Zero => &[0],
+                    Selection::One => &[1],
+                    Selection::Both => &[0, 1],
+                }
+            };
 
-fn life_support_rating(s: &str) -> u64 {
-    fn delve<'a>(lines: &[&'a str], prefer_one: bool, depth: usize) -> &'a str {
-        // Exit if we only have one string
-        if let Some((one, rest)) = lines.split_first() {
-            if rest.is_empty() {
-                return one;
+            next.clear();
+            for &(node, value) in &frontier {
+                for &bit in bits {
+                    if let Some(child) = self.nodes[node].children[bit] {
+                        next.push((child, value << 1 | bit as u64));
+                    }
+                }
             }
+            std::mem::swap(&mut frontier, &mut next);
         }
 
-        let (bit_0, bit_1): (Vec<_>, Vec<_>) = lines
+        match &*frontier {
+            [(_, value)] => Ok(*value),
+            _ => AmbiguousSnafu {
+                candidates: frontier
+                    .iter()
+                    .map(|(_, v)| format!("{:0width$b}", v, width = self.width))
+                    .collect::<Vec<_>>(),
+            }
+            .fail(),
+        }
+    }
+
+    fn count_children(&self, frontier: &[(usize, u64)], bit: usize) -> usize {
+        frontier
             .iter()
-            .partition(|l| l.chars().nth(depth) == Some('0'));
+            .flat_map(|&(node, _)| self.nodes[node].children[bit])
+            .map(|child| self.nodes[child].count)
+            .sum()
+    }
+}
 
-        use std::cmp::Ordering::*;
-        let selected = match (bit_0.len().cmp(&bit_1.len()), prefer_one) {
-            (Less, true) => bit_1,
-            (Equal, true) => bit_1,
-            (Greater, true) => bit_0,
-
-            (Less, false) => bit_0,
-            (Equal, false) => bit_0,
-            (Greater, false) => bit_1,
-        };
+/// The bit positions where the report has exactly as many zeros as ones.
+fn tied_positions(s: &str) -> Result<Vec<usize>> {
+    let columns = Report::new(s)?.column_counts();
 
-        delve(&selected, prefer_one, depth + 1)
+    Ok(columns
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.is_tie())
+        .map(|(p, _)| p)
+        .collect())
+}
+
+/// Every line of the report packed into a word, most significant bit
+/// first.
+#[derive(Debug)]
+struct Report {
+    rows: Vec<u64>,
+    width: usize,
+}
+
+impl Report {
+    fn new(s: &str) -> Result<Self> {
+        let mut rows = vec![];
+        let mut width = None;
+
+        for l in clean_lines(s) {
+            let row = u64::from_str_radix(l, 2).context(NotBinarySnafu)?;
+
+            let &mut expected = width.get_or_insert(l.len());
+            ensure!(
+                expected == l.len(),
+                WidthSnafu {
+                    expected,
+                    actual: l.len()
+                }
+            );
+
+            rows.push(row);
+        }
+
+        let width = width.context(EmptySnafu)?;
+        Ok(Self { rows, width })
     }
 
-    let lines: Vec<_> = clean_lines(s).collect();
-    let oxygen = delve(&lines, true, 0);
-    let co2 = delve(&lines, false, 0);
+    fn column_counts(&self) -> Vec<ColumnCount> {
+        let mut counter = ColumnCounter::default();
+        counter.add_rows(&self.rows);
+        let ones = counter.finish();
 
-    let oxygen = u64::from_str_radix(oxygen, 2).expect("Not binary");
-    let co2 = u64::from_str_radix(co2, 2).expect("Not binary");
+        let n_rows = self.rows.len();
+        (0..self.width)
+            .rev()
+            .map(|bit| ColumnCount {
+                zeros: n_rows - ones[bit],
+                ones: ones[bit],
+            })
+            .collect()
+    }
+}
 
-    oxygen * co2
+/// Counts the one bits in every column of many rows at once.
+///
+/// Each lane holds a vertical binary counter: bit `c` of
+/// `planes[i][lane]` is bit `i` of that lane's count for column `c`.
+/// Adding a row is then a ripple-carry add done with whole-word
+/// operations, and the lanes are independent so the inner loops can be
+/// vectorized. The counters are flushed into `totals` before they can
+/// overflow.
+#[derive(Debug)]
+struct ColumnCounter {
+    planes: [[u64; Self::LANES]; Self::PLANES],
+    pending: usize,
+    totals: [usize; 64],
+}
+
+impl Default for ColumnCounter {
+    fn default() -> Self {
+        Self {
+            planes: [[0; Self::LANES]; Self::PLANES],
+            pending: 0,
+            totals: [0; 64],
+        }
+    }
+}
+
+impl ColumnCounter {
+    const LANES: usize = 4;
+    const PLANES: usize = 16;
+    const CAPACITY: usize = (1 << Self::PLANES) - 1;
+
+    fn add_rows(&mut self, rows: &[u64]) {
+        let mut chunks = rows.chunks_exact(Self::LANES);
+
+        for chunk in &mut chunks {
+            let mut carry = [0; Self::LANES];
+            carry.copy_from_slice(chunk);
+            self.add_lanes(carry);
+        }
+
+        let mut carry = [0; Self::LANES];
+        carry[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+        self.add_lanes(carry);
+    }
+
+    fn add_lanes(&mut self, mut carry: [u64; Self::LANES]) {
+        for plane in &mut self.planes {
+            for (p, c) in plane.iter_mut().zip(&mut carry) {
+                let overflow = *p & *c;
+                *p ^= *c;
+                *c = overflow;
+            }
+
+            if carry.iter().all(|&c| c == 0) {
+                break;
+            }
+        }
+
+        self.pending += 1;
+        if self.pending == Self::CAPACITY {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        for (weight, plane) in self.planes.iter_mut().enumerate() {
+            for lane in plane.iter_mut() {
+                let mut bits = *lane;
+                while bits != 0 {
+                    let column = bits.trailing_zeros() as usize;
+                    self.totals[column] += 1 << weight;
+                    bits &= bits - 1;
+                }
+                *lane = 0;
+            }
+        }
+        self.pending = 0;
+    }
+
+    /// The number of one bits seen for each bit position, least
+    /// significant first.
+    fn finish(mut self) -> [usize; 64] {
+        self.flush();
+        self.totals
+    }
 }
 
 fn clean_lines(s: &str) -> impl Iterator<Item = &str> {
     s.lines().map(str::trim).filter(|s| !s.is_empty())
 }
 
+/// How each of the ratings picks a bit from a column.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Policy {
+    gamma: BitCriteria,
+    epsilon: BitCriteria,
+    oxygen: BitCriteria,
+    co2: BitCriteria,
+}
+
+impl Policy {
+    /// The rules from the submarine manual.
+    const STANDARD: Self = Self {
+        gamma: BitCriteria::new(Majority::MostCommon, TiePolicy::Error),
+        epsilon: BitCriteria::new(Majority::LeastCommon, TiePolicy::Error),
+        oxygen: BitCriteria::new(Majority::MostCommon, TiePolicy::PreferOne),
+        co2: BitCriteria::new(Majority::LeastCommon, TiePolicy::PreferZero),
+    };
+
+    /// The standard majorities, with every tie handled the same way.
+    fn with_ties(ties: TiePolicy) -> Self {
+        let Self {
+            gamma,
+            epsilon,
+            oxygen,
+            co2,
+        } = Self::STANDARD;
+
+        Self {
+            gamma: BitCriteria::new(gamma.majority, ties),
+            epsilon: BitCriteria::new(epsilon.majority, ties),
+            oxygen: BitCriteria::new(oxygen.majority, ties),
+            co2: BitCriteria::new(co2.majority, ties),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct BitCriteria {
+    majority: Majority,
+    ties: TiePolicy,
+}
+
+impl BitCriteria {
+    const fn new(majority: Majority, ties: TiePolicy) -> Self {
+        Self { majority, ties }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Majority {
+    MostCommon,
+    LeastCommon,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TiePolicy {
+    Error,
+    PreferZero,
+    PreferOne,
+    KeepBoth,
+}
+
+impl FromStr for TiePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use TiePolicy::*;
+
+        Ok(match s {
+            "error" => Error,
+            "prefer-zero" => PreferZero,
+            "prefer-one" => PreferOne,
+            "keep-both" => KeepBoth,
+            _ => return UnknownTiePolicySnafu { name: s }.fail(),
+        })
+    }
+}
+
+impl TiePolicy {
+    fn resolve(self) -> Option<Selection> {
+        use {Selection::*, TiePolicy::*};
+
+        match self {
+            Error => None,
+            PreferZero => Some(Zero),
+            PreferOne => Some(One),
+            KeepBoth => Some(Both),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Selection {
+    Zero,
+    One,
+    Both,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct ColumnCount {
+    zeros: usize,
+    ones: usize,
+}
+
+impl ColumnCount {
+    fn is_tie(&self) -> bool {
+        self.zeros == self.ones
+    }
+
+    /// `None` when the column is tied and the policy refuses to pick.
+    fn select(&self, criteria: BitCriteria) -> Option<Selection> {
+        use std::cmp::Ordering::*;
+        use {Majority::*, Selection::*};
+
+        match (self.zeros.cmp(&self.ones), criteria.majority) {
+            (Greater, MostCommon) | (Less, LeastCommon) => Some(Zero),
+            (Less, MostCommon) | (Greater, LeastCommon) => Some(One),
+            (Equal, _) => criteria.ties.resolve(),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+enum Error {
+    Empty,
+    Tie { positions: Vec<usize> },
+    Ambiguous { candidates: Vec<String> },
+    Width { expected: usize, actual: usize },
+    NotBinary { source: ParseIntError },
+    UnknownTiePolicy { name: String },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -100,11 +480,112 @@ mod test {
 
     #[test]
     fn test_part_1() {
-        assert_eq!(198, gamma_epsilon_product(TEST_INPUT));
+        assert_eq!(198, gamma_epsilon_product(TEST_INPUT, &Policy::STANDARD));
     }
 
     #[test]
     fn test_part_2() {
-        assert_eq!(230, life_support_rating(TEST_INPUT));
+        assert_eq!(230, life_support_rating(TEST_INPUT, &Policy::STANDARD));
+    }
+
+    const TIED_INPUT: &str = r#"
+0100
+1101
+1100
+0011
+"#;
+
+    #[test]
+    fn test_ties_reported_per_position() {
+        assert_eq!(vec![0, 3], tied_positions(TIED_INPUT).unwrap());
+
+        let e = gamma_epsilon_product_inner(TIED_INPUT, &Policy::STANDARD).unwrap_err();
+        assert!(matches!(e, Error::Tie { positions } if positions == [0, 3]));
+    }
+
+    #[test]
+    fn test_tie_preferences() {
+        let policy = Policy {
+            gamma: BitCriteria::new(Majority::MostCommon, TiePolicy::PreferOne),
+            epsilon: BitCriteria::new(Majority::LeastCommon, TiePolicy::PreferZero),
+            ..Policy::STANDARD
+        };
+
+        // gamma = 0b1101, epsilon = 0b0010
+        assert_eq!(
+            26,
+            gamma_epsilon_product_inner(TIED_INPUT, &policy).unwrap()
+        );
+
+        let policy = Policy {
+            oxygen: BitCriteria::new(Majority::MostCommon, TiePolicy::Error),
+            ..Policy::STANDARD
+        };
+        let e = life_support_rating_inner(TIED_INPUT, &policy).unwrap_err();
+        assert!(matches!(e, Error::Tie { positions } if positions == [0]));
+    }
+
+    #[test]
+    fn test_policy_with_ties() {
+        let policy = Policy::with_ties("prefer-zero".parse().unwrap());
+        assert_eq!(Majority::LeastCommon, policy.epsilon.majority);
+        assert_eq!(TiePolicy::PreferZero, policy.oxygen.ties);
+
+        // gamma = 0b0100, epsilon = 0b0010
+        assert_eq!(8, gamma_epsilon_product(TIED_INPUT, &policy));
+
+        assert!(matches!(
+            "sometimes".parse::<TiePolicy>(),
+            Err(Error::UnknownTiePolicy { name }) if name == "sometimes"
+        ));
+    }
+
+    #[test]
+    fn test_tie_keep_both() {
+        let policy = Policy {
+            oxygen: BitCriteria::new(Majority::MostCommon, TiePolicy::KeepBoth),
+            ..Policy::STANDARD
+        };
+
+        // Bit 0 is tied so both halves are kept, leaving 0100 and 1100 at the end
+        let e = life_support_rating_inner(TIED_INPUT, &policy).unwrap_err();
+        assert!(matches!(e, Error::Ambiguous { candidates } if candidates == ["0100", "1100"]));
+    }
+
+    #[test]
+    fn test_trie_ratings() {
+        let report = Report::new(TEST_INPUT).unwrap();
+        let trie = Trie::new(&report);
+
+        assert_eq!(12, trie.nodes[Trie::ROOT].count);
+        assert_eq!(23, trie.rating(Policy::STANDARD.oxygen).unwrap());
+        assert_eq!(10, trie.rating(Policy::STANDARD.co2).unwrap());
+    }
+
+    #[test]
+    fn test_bit_sliced_counts_match_naive() {
+        // Enough rows to flush the counters a few times, plus a ragged
+        // final chunk
+        let n_rows = ColumnCounter::LANES * ColumnCounter::CAPACITY * 2 + 3;
+        let width = 40;
+        // Column `p` flips every `p + 1` rows, so each column counts
+        // differently
+        let rows: Vec<_> = (0..n_rows)
+            .map(|i| (0..width).fold(0, |row, p| row << 1 | (i / (p + 1) % 2) as u64))
+            .collect();
+        let report = Report { rows, width };
+
+        let mut naive = vec![ColumnCount::default(); report.width];
+        for row in &report.rows {
+            for (p, column) in naive.iter_mut().enumerate() {
+                if row >> (report.width - 1 - p) & 1 == 1 {
+                    column.ones += 1;
+                } else {
+                    column.zeros += 1;
+                }
+            }
+        }
+
+        assert_eq!(naive, report.column_counts());
     }
 }
@@ -1,129 +1,613 @@
-#![feature(vec_retain_mut)]
-
 use itertools::Itertools;
-use std::collections::BTreeMap;
+use snafu::{ensure, OptionExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    io::{self, IsTerminal},
+    mem,
+    num::ParseIntError,
+    str::FromStr,
+};
 
 const INPUT: &str = include_str!("../input");
 
 fn main() {
+    let renderer: &dyn Renderer = if io::stdout().is_terminal() {
+        &Ansi
+    } else {
+        &PlainText
+    };
+
+    let mut options = Options {
+        show: Some(renderer),
+        ..Options::default()
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            // e.g. `--rules rows,diagonals,mask:0.0+4.4`
+            "--rules" => {
+                let rules = args.next().expect("--rules needs a value");
+                options.rules = rules.split(',').map(|r| r.parse().unwrap()).collect();
+            }
+            "--lenient" => options.mode = ParseMode::Lenient,
+            "--html" => options.show = Some(&Html),
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+
     // Wrong: 0 -- there are more than 3 boards
-    println!("part1: {}", first_winning_score(INPUT));
-    println!("part2: {}", last_winning_score(INPUT));
+    println!("part1: {}", first_winning_score(INPUT, &options));
+    println!("part2: {}", last_winning_score(INPUT, &options));
+}
+
+/// How to play and what to show.
+struct Options<'a> {
+    rules: Vec<WinPattern>,
+    mode: ParseMode,
+    /// Draws each winning board
+    show: Option<&'a dyn Renderer>,
 }
 
-fn first_winning_score(s: &str) -> u64 {
-    let (calls, mut boards) = parse_input(s);
+impl Default for Options<'_> {
+    fn default() -> Self {
+        Self {
+            rules: WinPattern::STANDARD.to_vec(),
+            mode: ParseMode::Strict,
+            show: None,
+        }
+    }
+}
 
-    for call in calls {
-        for board in &mut boards {
-            board_mark_call(board, call);
+fn first_winning_score(s: &str, options: &Options) -> u64 {
+    let timeline = simulate(s, options);
+    let (board, win) = timeline.first_winner().expect("no winners");
 
-            if board_is_win(board) {
-                print_board(board);
-                return board_sum_of_unmarked(board) * u64::from(call);
-            }
+    if let Some(renderer) = options.show {
+        for problem in &timeline.problems {
+            eprintln!("skipped: {problem:?}");
         }
+        print!(
+            "{}",
+            renderer.render(&timeline.boards[board], timeline.dimension)
+        );
     }
+    win.score
+}
 
-    panic!("no winners");
+fn last_winning_score(s: &str, options: &Options) -> u64 {
+    let timeline = simulate(s, options);
+    let (board, win) = timeline.last_winner().expect("no winners");
+
+    if let Some(renderer) = options.show {
+        println!("boards won in order: {:?}", timeline.win_order());
+        print!(
+            "{}",
+            renderer.render(&timeline.boards[board], timeline.dimension)
+        );
+    }
+    win.score
 }
 
-fn last_winning_score(s: &str) -> u64 {
-    let (mut calls, mut boards) = parse_input(s);
+fn simulate(s: &str, options: &Options) -> Timeline {
+    simulate_inner(s, &options.rules, options.mode).unwrap()
+}
 
-    for call in &mut calls {
-        boards.retain_mut(|board| {
-            board_mark_call(board, call);
-            !board_is_win(board)
-        });
+/// Plays every call against every board, stopping a board as soon as it
+/// wins. Each call only visits the cells holding that number.
+fn simulate_inner(s: &str, rules: &[WinPattern], mode: ParseMode) -> Result<Timeline> {
+    let Input {
+        calls,
+        mut boards,
+        dimension,
+        problems,
+    } = parse_input(s, mode)?;
+    let patterns = win_patterns(rules, dimension);
+    let index = CallIndex::new(&boards, &patterns);
+
+    let mut hits = vec![vec![0; patterns.len()]; boards.len()];
+    let mut outcomes = vec![None; boards.len()];
+    let mut remaining = boards.len();
 
-        if boards.len() == 1 {
+    for (call_index, call) in calls.into_iter().enumerate() {
+        if remaining == 0 {
             break;
         }
+
+        let by_board = index
+            .locations(call)
+            .iter()
+            .group_by(|&&(board_id, _)| board_id);
+
+        for (board_id, locations) in &by_board {
+            if outcomes[board_id].is_some() {
+                continue;
+            }
+
+            // Mark every cell holding the call before scoring, as a
+            // board may hold the same number more than once
+            let board = &mut boards[board_id];
+            let hits = &mut hits[board_id];
+            let mut won = None;
+            for &(_, cell) in locations {
+                let (_, marked) = board.get_mut(&cell).expect("Index is out of date");
+                if mem::replace(marked, true) {
+                    // This number was already called
+                    continue;
+                }
+
+                for &p in index.patterns_containing(cell) {
+                    hits[p] += 1;
+                    if hits[p] == patterns[p].len() {
+                        won.get_or_insert(p);
+                    }
+                }
+            }
+
+            if let Some(p) = won {
+                outcomes[board_id] = Some(Win {
+                    call_index,
+                    call,
+                    pattern: patterns[p].clone(),
+                    score: board_sum_of_unmarked(board) * u64::from(call),
+                });
+                remaining -= 1;
+            }
+        }
     }
 
-    let mut last = boards.pop().expect("Must have one board left");
+    Ok(Timeline {
+        boards,
+        dimension,
+        outcomes,
+        problems,
+    })
+}
+
+/// Where every number appears across all the boards, and which patterns
+/// every cell contributes to.
+#[derive(Debug, Default)]
+struct CallIndex {
+    locations: BTreeMap<u8, Vec<(usize, Cell)>>,
+    memberships: BTreeMap<Cell, Vec<usize>>,
+}
 
-    for call in calls {
-        board_mark_call(&mut last, call);
+impl CallIndex {
+    fn new(boards: &[Board], patterns: &[Pattern]) -> Self {
+        let mut this = Self::default();
 
-        if board_is_win(&last) {
-            print_board(&last);
-            return board_sum_of_unmarked(&last) * u64::from(call);
+        for (board_id, board) in boards.iter().enumerate() {
+            for (&cell, &(v, _)) in board {
+                this.locations.entry(v).or_default().push((board_id, cell));
+            }
         }
+
+        for (p, pattern) in patterns.iter().enumerate() {
+            for &cell in pattern {
+                this.memberships.entry(cell).or_default().push(p);
+            }
+        }
+
+        this
+    }
+
+    /// Every `(board, cell)` holding `call`, in board order.
+    fn locations(&self, call: u8) -> &[(usize, Cell)] {
+        self.locations.get(&call).map_or(&[], Vec::as_slice)
+    }
+
+    fn patterns_containing(&self, cell: Cell) -> &[usize] {
+        self.memberships.get(&cell).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[derive(Debug)]
+struct Timeline {
+    /// Each board as it was when it won, or after the last call
+    boards: Vec<Board>,
+    dimension: usize,
+    /// `None` for boards that never win
+    outcomes: Vec<Option<Win>>,
+    /// Anything that was skipped while leniently parsing
+    problems: Vec<Error>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Win {
+    call_index: usize,
+    call: u8,
+    pattern: Pattern,
+    score: u64,
+}
+
+impl Timeline {
+    fn wins(&self) -> impl Iterator<Item = (usize, &Win)> {
+        self.outcomes
+            .iter()
+            .enumerate()
+            .flat_map(|(board, outcome)| Some((board, outcome.as_ref()?)))
+    }
+
+    /// Boards that win on the same call are ordered as in the input.
+    fn first_winner(&self) -> Option<(usize, &Win)> {
+        self.wins().min_by_key(|(_, win)| win.call_index)
     }
 
-    panic!("no winners");
+    fn last_winner(&self) -> Option<(usize, &Win)> {
+        self.wins().max_by_key(|(_, win)| win.call_index)
+    }
+
+    /// The boards that win, in the order they win.
+    fn win_order(&self) -> Vec<usize> {
+        self.wins()
+            .sorted_by_key(|(_, win)| win.call_index)
+            .map(|(board, _)| board)
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ParseMode {
+    /// Any problem with the input is an error
+    Strict,
+    /// Skip unparseable calls and unusable boards, reporting them
+    /// alongside the result. Boards that repeat a number are kept, and
+    /// every copy is marked when it is called.
+    Lenient,
 }
 
-fn parse_input(s: &str) -> (impl Iterator<Item = u8> + '_, Vec<Board>) {
-    let mut lines = s.lines().peekable();
-    let calls = lines.next().expect("Missing calls");
-    let calls = calls.split(',').flat_map(str::parse);
+#[derive(Debug)]
+struct Input {
+    calls: Vec<u8>,
+    boards: Vec<Board>,
+    dimension: usize,
+    problems: Vec<Error>,
+}
+
+fn parse_input(s: &str, mode: ParseMode) -> Result<Input> {
+    let mut lines = s.lines().enumerate().map(|(i, l)| (i + 1, l)).peekable();
+    let mut problems = vec![];
+
+    let (line, calls) = lines.next().context(MissingCallsSnafu)?;
+    let calls = parse_calls(line, calls, &mut problems);
 
     let mut boards = vec![];
-    while lines.peek().is_some() {
-        boards.push(parse_board(&mut lines));
+    let mut dimension = None;
+    loop {
+        while lines.next_if(|(_, l)| l.trim().is_empty()).is_some() {}
+        let line = match lines.peek() {
+            Some(&(line, _)) => line,
+            None => break,
+        };
+
+        let rows: Vec<_> = lines
+            .by_ref()
+            .take_while(|(_, l)| !l.trim().is_empty())
+            .collect();
+
+        let n_problems = problems.len();
+        let board = parse_board(&rows, &mut problems).filter(|&(_, size)| {
+            let &mut expected = dimension.get_or_insert(size);
+            if size != expected {
+                problems.push(Error::BoardSize {
+                    line,
+                    expected,
+                    actual: size,
+                });
+            }
+            size == expected
+        });
+
+        match board {
+            Some((board, _)) => boards.push(board),
+            None => problems.push(Error::SkippedBoard {
+                line,
+                n_problems: problems.len() - n_problems,
+            }),
+        }
+    }
+
+    if mode == ParseMode::Strict {
+        ensure!(problems.is_empty(), MalformedSnafu { problems });
     }
 
-    (calls, boards)
+    Ok(Input {
+        calls,
+        boards,
+        dimension: dimension.unwrap_or(0),
+        problems,
+    })
 }
 
-type Board = BTreeMap<(usize, usize), (u8, bool)>;
-const BOARD_DIMENSION: usize = 5;
+fn parse_calls(line: usize, s: &str, problems: &mut Vec<Error>) -> Vec<u8> {
+    let mut seen = BTreeSet::new();
+    let mut calls = vec![];
+
+    for call in s.split(',').map(str::trim) {
+        match call.parse() {
+            Ok(call) => {
+                if !seen.insert(call) {
+                    problems.push(Error::DuplicateCall { line, call });
+                }
+                calls.push(call);
+            }
+            Err(source) => problems.push(Error::InvalidCall {
+                line,
+                call: call.into(),
+                source,
+            }),
+        }
+    }
+
+    calls
+}
+
+/// An `(x, y)` position on a board
+type Cell = (usize, usize);
+type Board = BTreeMap<Cell, (u8, bool)>;
+
+/// Returns the board and its size, or `None` if it is unusable. Every
+/// row must be as long as the board is tall.
+fn parse_board(rows: &[(usize, &str)], problems: &mut Vec<Error>) -> Option<(Board, usize)> {
+    let dimension = rows.len();
 
-fn parse_board<'a>(lines: impl Iterator<Item = &'a str>) -> Board {
     let mut board = BTreeMap::new();
-    for (y, l) in lines.dropping(1).take(BOARD_DIMENSION).enumerate() {
-        for (x, c) in l.split_ascii_whitespace().enumerate() {
-            let c = c.parse().expect("Invalid digit");
-            board.insert((x, y), (c, false));
+    let mut seen = BTreeSet::new();
+    let mut usable = true;
+
+    for (y, &(line, l)) in rows.iter().enumerate() {
+        let cells: Vec<_> = l.split_ascii_whitespace().collect();
+        if cells.len() != dimension {
+            problems.push(Error::RowLength {
+                line,
+                expected: dimension,
+                actual: cells.len(),
+            });
+            usable = false;
+        }
+
+        for (x, c) in cells.into_iter().enumerate() {
+            match c.parse() {
+                Ok(number) => {
+                    if !seen.insert(number) {
+                        problems.push(Error::DuplicateNumber { line, number });
+                    }
+                    board.insert((x, y), (number, false));
+                }
+                Err(source) => {
+                    problems.push(Error::InvalidCell {
+                        line,
+                        cell: c.into(),
+                        source,
+                    });
+                    usable = false;
+                }
+            }
         }
     }
-    board
+
+    usable.then_some((board, dimension))
 }
 
-fn print_board(this: &Board) {
-    for y in 0..BOARD_DIMENSION {
-        for x in 0..BOARD_DIMENSION {
-            let &(v, marked) = this.get(&(x, y)).unwrap();
-            if marked {
-                print!("\x1b[1m{:02}\x1b[0m ", v);
-            } else {
-                print!("{:02} ", v);
+/// Turns a board into text. Implementors decide how each cell looks and
+/// may wrap the rows and the board as a whole.
+trait Renderer {
+    fn cell(&self, out: &mut String, value: u8, marked: bool);
+
+    fn begin_board(&self, _out: &mut String) {}
+
+    fn end_board(&self, _out: &mut String) {}
+
+    fn begin_row(&self, _out: &mut String) {}
+
+    fn end_row(&self, out: &mut String) {
+        out.push('\n');
+    }
+
+    fn render(&self, board: &Board, dimension: usize) -> String {
+        let mut out = String::new();
+
+        self.begin_board(&mut out);
+        for y in 0..dimension {
+            self.begin_row(&mut out);
+            for x in 0..dimension {
+                let &(v, marked) = board.get(&(x, y)).expect("Board is missing a cell");
+                self.cell(&mut out, v, marked);
             }
+            self.end_row(&mut out);
+        }
+        self.end_board(&mut out);
+
+        out
+    }
+}
+
+/// Marked cells are surrounded by brackets.
+struct PlainText;
+
+impl Renderer for PlainText {
+    fn cell(&self, out: &mut String, value: u8, marked: bool) {
+        if marked {
+            out.push_str(&format!("[{:02}]", value));
+        } else {
+            out.push_str(&format!(" {:02} ", value));
         }
-        println!();
     }
 }
 
-fn board_mark_call(this: &mut Board, call: u8) {
-    for (num, seen) in this.values_mut() {
-        if *num == call {
-            *seen = true;
+/// Marked cells are bold.
+struct Ansi;
+
+impl Renderer for Ansi {
+    fn cell(&self, out: &mut String, value: u8, marked: bool) {
+        if marked {
+            out.push_str(&format!("\x1b[1m{:02}\x1b[0m ", value));
+        } else {
+            out.push_str(&format!("{:02} ", value));
         }
     }
 }
 
-fn board_is_win(this: &Board) -> bool {
-    board_is_win_vertical(this) || board_is_win_horizontal(this)
+/// A `<table>`; marked cells have the `marked` class.
+struct Html;
+
+impl Renderer for Html {
+    fn cell(&self, out: &mut String, value: u8, marked: bool) {
+        if marked {
+            out.push_str(&format!(r#"<td class="marked">{}</td>"#, value));
+        } else {
+            out.push_str(&format!("<td>{}</td>", value));
+        }
+    }
+
+    fn begin_board(&self, out: &mut String) {
+        out.push_str("<table>\n");
+    }
+
+    fn end_board(&self, out: &mut String) {
+        out.push_str("</table>\n");
+    }
+
+    fn begin_row(&self, out: &mut String) {
+        out.push_str("<tr>");
+    }
+
+    fn end_row(&self, out: &mut String) {
+        out.push_str("</tr>\n");
+    }
 }
 
-fn board_is_win_vertical(this: &Board) -> bool {
-    (0..BOARD_DIMENSION)
-        .any(|x| (0..BOARD_DIMENSION).all(|y| this.get(&(x, y)).map_or(false, |(_, v)| *v)))
+/// A set of cells that wins once every one of them is marked.
+type Pattern = BTreeSet<Cell>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WinPattern {
+    Rows,
+    Columns,
+    /// Both the leading and trailing diagonals
+    Diagonals,
+    FourCorners,
+    /// Every cell on the board
+    Blackout,
+    /// Any arbitrary set of cells
+    Mask(Pattern),
 }
-fn board_is_win_horizontal(this: &Board) -> bool {
-    (0..BOARD_DIMENSION)
-        .any(|y| (0..BOARD_DIMENSION).all(|x| this.get(&(x, y)).map_or(false, |(_, v)| *v)))
+
+impl WinPattern {
+    const STANDARD: &'static [Self] = &[WinPattern::Rows, WinPattern::Columns];
+
+    fn patterns(&self, dimension: usize) -> Vec<Pattern> {
+        use WinPattern::*;
+
+        let last = dimension.saturating_sub(1);
+
+        match self {
+            Rows => (0..dimension)
+                .map(|y| (0..dimension).map(|x| (x, y)).collect())
+                .collect(),
+            Columns => (0..dimension)
+                .map(|x| (0..dimension).map(|y| (x, y)).collect())
+                .collect(),
+            Diagonals => vec![
+                (0..dimension).map(|i| (i, i)).collect(),
+                (0..dimension).map(|i| (last - i, i)).collect(),
+            ],
+            FourCorners => vec![[(0, 0), (last, 0), (0, last), (last, last)].into()],
+            Blackout => vec![(0..dimension).cartesian_product(0..dimension).collect()],
+            Mask(cells) => vec![cells.clone()],
+        }
+    }
+}
+
+/// One of `rows`, `columns`, `diagonals`, `corners`, `blackout`, or
+/// `mask:` followed by `x.y` cells joined with `+`.
+impl FromStr for WinPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use WinPattern::*;
+
+        Ok(match s {
+            "rows" => Rows,
+            "columns" => Columns,
+            "diagonals" => Diagonals,
+            "corners" => FourCorners,
+            "blackout" => Blackout,
+            _ => {
+                let cells = s
+                    .strip_prefix("mask:")
+                    .context(UnknownRuleSnafu { rule: s })?;
+                let cells = cells.split('+').map(|c| {
+                    let (x, y) = c.split_once('.')?;
+                    Some((x.parse().ok()?, y.parse().ok()?))
+                });
+                Mask(
+                    cells
+                        .collect::<Option<_>>()
+                        .context(UnknownRuleSnafu { rule: s })?,
+                )
+            }
+        })
+    }
+}
+
+fn win_patterns(rules: &[WinPattern], dimension: usize) -> Vec<Pattern> {
+    rules.iter().flat_map(|r| r.patterns(dimension)).collect()
 }
 
 fn board_sum_of_unmarked(this: &Board) -> u64 {
     this.values()
-        .filter_map(|(v, marked)| (!marked).then(|| u64::from(*v)))
+        .filter(|(_, marked)| !marked)
+        .map(|&(v, _)| u64::from(v))
         .sum()
 }
 
+#[derive(Debug, Snafu)]
+enum Error {
+    MissingCalls,
+    InvalidCall {
+        line: usize,
+        call: String,
+        source: ParseIntError,
+    },
+    DuplicateCall {
+        line: usize,
+        call: u8,
+    },
+    InvalidCell {
+        line: usize,
+        cell: String,
+        source: ParseIntError,
+    },
+    RowLength {
+        line: usize,
+        expected: usize,
+        actual: usize,
+    },
+    DuplicateNumber {
+        line: usize,
+        number: u8,
+    },
+    BoardSize {
+        line: usize,
+        expected: usize,
+        actual: usize,
+    },
+    SkippedBoard {
+        line: usize,
+        n_problems: usize,
+    },
+    Malformed {
+        problems: Vec<Error>,
+    },
+    UnknownRule {
+        rule: String,
+    },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -132,11 +616,208 @@ mod test {
 
     #[test]
     fn test_part_1() {
-        assert_eq!(4512, first_winning_score(TEST_INPUT));
+        assert_eq!(4512, first_winning_score(TEST_INPUT, &Options::default()));
     }
 
     #[test]
     fn test_part_2() {
-        assert_eq!(1924, last_winning_score(TEST_INPUT));
+        assert_eq!(1924, last_winning_score(TEST_INPUT, &Options::default()));
+    }
+
+    fn with_rules(rules: &[WinPattern]) -> Options<'static> {
+        Options {
+            rules: rules.to_vec(),
+            ..Options::default()
+        }
+    }
+
+    const SMALL_INPUT: &str = r#"5,3,7,1,9,2
+
+1 2 3
+4 5 6
+7 8 9
+
+9 8 7
+6 5 4
+3 2 1
+"#;
+
+    #[test]
+    fn test_dimension_from_input() {
+        let input = parse_input(SMALL_INPUT, ParseMode::Strict).unwrap();
+        assert_eq!(2, input.boards.len());
+        assert_eq!(3, input.dimension);
+
+        // The first board completes the 3-5-7 diagonal after the 7 is called
+        let rules = [WinPattern::Diagonals];
+        assert_eq!(
+            (1 + 2 + 4 + 6 + 8 + 9) * 7,
+            first_winning_score(SMALL_INPUT, &with_rules(&rules))
+        );
+    }
+
+    #[test]
+    fn test_custom_win_patterns() {
+        let rules = [WinPattern::FourCorners];
+        assert_eq!(
+            (2 + 4 + 6 + 8) * 9,
+            first_winning_score(SMALL_INPUT, &with_rules(&rules))
+        );
+
+        // The second board is the last to complete its top-left to centre mask
+        let rules = [WinPattern::Mask([(0, 0), (1, 1)].into())];
+        assert_eq!(
+            (8 + 6 + 4 + 2) * 9,
+            last_winning_score(SMALL_INPUT, &with_rules(&rules))
+        );
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(WinPattern::FourCorners, "corners".parse().unwrap());
+        assert_eq!(
+            WinPattern::Mask([(0, 0), (1, 1)].into()),
+            "mask:0.0+1.1".parse().unwrap()
+        );
+        assert!(matches!(
+            "mask:0.0+1".parse::<WinPattern>(),
+            Err(Error::UnknownRule { rule }) if rule == "mask:0.0+1"
+        ));
+        assert!("zigzag".parse::<WinPattern>().is_err());
+    }
+
+    #[test]
+    fn test_timeline() {
+        let timeline = simulate(TEST_INPUT, &Options::default());
+
+        assert_eq!(vec![2, 0, 1], timeline.win_order());
+
+        let win = timeline.outcomes[2].as_ref().unwrap();
+        assert_eq!(11, win.call_index);
+        assert_eq!(24, win.call);
+        assert_eq!(win_patterns(&[WinPattern::Rows], 5)[0], win.pattern);
+        assert_eq!(4512, win.score);
+    }
+
+    #[test]
+    fn test_timeline_never_wins() {
+        let rules = [WinPattern::Mask([(1, 0), (1, 1)].into())];
+        let timeline = simulate(SMALL_INPUT, &with_rules(&rules));
+
+        // The second board needs an 8, which is never called
+        assert_eq!(vec![0], timeline.win_order());
+        assert_eq!(None, timeline.outcomes[1]);
+    }
+
+    #[test]
+    fn test_repeated_calls_are_not_double_counted() {
+        let input = "1,1,2\n\n1 2\n3 4\n";
+
+        let timeline = simulate_inner(input, WinPattern::STANDARD, ParseMode::Lenient).unwrap();
+        let win = timeline.outcomes[0].as_ref().unwrap();
+        assert_eq!(2, win.call_index);
+        assert_eq!((3 + 4) * 2, win.score);
+    }
+
+    #[test]
+    fn test_repeated_numbers_are_all_marked() {
+        let input = "2,1\n\n1 2\n3 1\n";
+
+        let timeline = simulate_inner(input, WinPattern::STANDARD, ParseMode::Lenient).unwrap();
+        let win = timeline.outcomes[0].as_ref().unwrap();
+        assert_eq!(1, win.call_index);
+        assert_eq!(3, win.score);
+    }
+
+    const MALFORMED_INPUT: &str = r#"1,2,x,3,2
+
+1 2
+3 4
+
+5 6
+7
+
+8 9 10
+11 12 13
+14 15 15
+"#;
+
+    #[test]
+    fn test_strict_refuses_malformed_games() {
+        let e = parse_input(MALFORMED_INPUT, ParseMode::Strict).unwrap_err();
+        let problems = match e {
+            Error::Malformed { problems } => problems,
+            e => panic!("Unexpected error {e:?}"),
+        };
+
+        assert!(matches!(
+            problems[..],
+            [
+                Error::InvalidCall { line: 1, .. },
+                Error::DuplicateCall { line: 1, call: 2 },
+                Error::RowLength {
+                    line: 7,
+                    expected: 2,
+                    actual: 1
+                },
+                Error::SkippedBoard {
+                    line: 6,
+                    n_problems: 1
+                },
+                Error::DuplicateNumber {
+                    line: 11,
+                    number: 15
+                },
+                Error::BoardSize {
+                    line: 9,
+                    expected: 2,
+                    actual: 3
+                },
+                // The duplicate and the size are both on this board
+                Error::SkippedBoard {
+                    line: 9,
+                    n_problems: 2
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_lenient_skips_malformed_parts() {
+        let input = parse_input(MALFORMED_INPUT, ParseMode::Lenient).unwrap();
+
+        assert_eq!(vec![1, 2, 3, 2], input.calls);
+        assert_eq!(1, input.boards.len());
+        assert_eq!(7, input.problems.len());
+
+        let timeline =
+            simulate_inner(MALFORMED_INPUT, WinPattern::STANDARD, ParseMode::Lenient).unwrap();
+        assert_eq!((3 + 4) * 2, timeline.first_winner().unwrap().1.score);
+    }
+
+    #[test]
+    fn test_renderers() {
+        let timeline = simulate(SMALL_INPUT, &with_rules(&[WinPattern::Diagonals]));
+        let board = &timeline.boards[0];
+
+        assert_eq!(
+            " 01  02 [03]\n 04 [05] 06 \n[07] 08  09 \n",
+            PlainText.render(board, 3),
+        );
+        assert!(Ansi
+            .render(board, 3)
+            .starts_with("01 02 \x1b[1m03\x1b[0m \n"));
+        assert_eq!(
+            concat!(
+                "<table>\n",
+                r#"<tr><td>1</td><td>2</td><td class="marked">3</td></tr>"#,
+                "\n",
+                r#"<tr><td>4</td><td class="marked">5</td><td>6</td></tr>"#,
+                "\n",
+                r#"<tr><td class="marked">7</td><td>8</td><td>9</td></tr>"#,
+                "\n</table>\n",
+            ),
+            Html.render(board, 3),
+        );
     }
 }
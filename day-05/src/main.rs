@@ -1,106 +1,722 @@
 use itertools::Itertools;
-use std::{collections::BTreeMap, iter, ops::RangeInclusive};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BTreeMap,
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
 
 const INPUT: &str = include_str!("../input");
 
 fn main() {
     println!("part1: {}", number_overlapping_points(INPUT, false));
     println!("part2: {}", number_overlapping_points(INPUT, true));
+
+    use LineClass::*;
+    let field = VentField::parse(INPUT, &[Horizontal, Vertical, Diagonal]);
+
+    let mut heatmap_path = None;
+    let mut crop = None;
+    let mut ramp = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            // The `n` points covered by the most part 2 vents
+            "--top" => {
+                let n = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .expect("--top needs a count");
+                for ((x, y), overlaps) in field.most_dangerous(n) {
+                    println!("{x},{y}: {overlaps}");
+                }
+            }
+            // How many part 2 vents cover a point, given as `x,y`
+            "--at" => {
+                let (x, y) = args
+                    .next()
+                    .as_deref()
+                    .and_then(parse_coord)
+                    .expect("--at needs a point like 3,4");
+                println!("{x},{y}: {}", field.overlaps_at((x, y)));
+            }
+            // Draws the part 2 vents to a `.pgm` or `.png` file
+            "--heatmap" => heatmap_path = Some(args.next().expect("--heatmap needs a path")),
+            // Only draws the points from `x1,y1` to `x2,y2`
+            "--crop" => {
+                let region = args.next().as_deref().and_then(parse_region);
+                crop = Some(region.expect("--crop needs x1,y1,x2,y2"));
+            }
+            "--ramp" => {
+                ramp = match args.next().as_deref() {
+                    Some("gray") => Some(ColorRamp::grayscale()),
+                    Some("heat") => Some(ColorRamp::heat()),
+                    other => panic!("Unknown ramp {other:?}"),
+                }
+            }
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+
+    if let Some(path) = heatmap_path {
+        write_heatmap(&field, Path::new(&path), crop, ramp).expect("Unable to write heatmap");
+    }
 }
 
-type Coord = (u64, u64);
-type Grid = BTreeMap<Coord, usize>;
+/// Without a `ramp`, `.pgm` files are gray and anything else is a heat
+/// map.
+fn write_heatmap(
+    field: &VentField,
+    path: &Path,
+    crop: Option<Region>,
+    ramp: Option<ColorRamp>,
+) -> io::Result<()> {
+    let heatmap = field.heatmap(crop).ok_or_else(|| {
+        let message = "Too many points to draw; crop to a smaller region";
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    })?;
+    let mut file = BufWriter::new(File::create(path)?);
+
+    if path.extension().is_some_and(|e| e == "pgm") {
+        let ramp = ramp.unwrap_or_else(ColorRamp::grayscale);
+        heatmap.write_pgm(&ramp, &mut file)?;
+    } else {
+        let ramp = ramp.unwrap_or_else(ColorRamp::heat);
+        heatmap.write_png(&ramp, &mut file)?;
+    }
+
+    file.flush()
+}
 
 fn number_overlapping_points(s: &str, include_diagonals: bool) -> usize {
-    let lines = s.lines().flat_map(|l| {
+    use LineClass::*;
+
+    let classes: &[_] = if include_diagonals {
+        &[Horizontal, Vertical, Diagonal]
+    } else {
+        &[Horizontal, Vertical]
+    };
+
+    number_overlapping_points_with(s, classes)
+}
+
+type Coord = (i64, i64);
+
+fn number_overlapping_points_with(s: &str, classes: &[LineClass]) -> usize {
+    VentField::parse(s, classes).count_at_least(2)
+}
+
+/// Answers questions about how vents overlap without visiting every
+/// point: overlaps between vents on the same line come from a sweep
+/// along that line, and the remaining overlaps can only be where two
+/// lines cross.
+#[derive(Debug)]
+struct VentField {
+    /// Sorted by direction and then offset
+    lines: Vec<Line>,
+    directions: Vec<Direction>,
+    crossings: BTreeMap<Coord, BTreeMap<usize, usize>>,
+}
+
+impl VentField {
+    fn parse(s: &str, classes: &[LineClass]) -> Self {
+        let segments = parse_segments(s).filter(|segment| classes.contains(&segment.class()));
+        Self::new(segments)
+    }
+
+    fn new(segments: impl IntoIterator<Item = Segment>) -> Self {
+        let lines = merge_collinear(segments);
+        let directions = lines.iter().map(|l| l.direction).dedup().collect();
+        let crossings = crossings(&lines);
+
+        Self {
+            lines,
+            directions,
+            crossings,
+        }
+    }
+
+    /// How many vents cover a point.
+    fn overlaps_at(&self, coord: Coord) -> usize {
+        if let Some(depths) = self.crossings.get(&coord) {
+            return depths.values().sum();
+        }
+
+        // Not a crossing, so at most one line covers it
+        self.directions
+            .iter()
+            .find_map(|direction| {
+                let key = (*direction, direction.offset(coord));
+                let idx = self
+                    .lines
+                    .binary_search_by_key(&key, |l| (l.direction, l.offset))
+                    .ok()?;
+                self.lines[idx].depth_at(coord)
+            })
+            .unwrap_or(0)
+    }
+
+    /// The number of points covered by exactly each number of vents.
+    /// Uncovered points are not included.
+    fn histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+
+        for line in &self.lines {
+            for piece in &line.pieces {
+                *histogram.entry(piece.depth).or_default() += line.len(piece);
+            }
+        }
+
+        // Crossings were counted once for each line through them
+        for depths in self.crossings.values() {
+            for depth in depths.values() {
+                *histogram.get_mut(depth).expect("Crossing is not on a line") -= 1;
+            }
+            *histogram.entry(depths.values().sum()).or_default() += 1;
+        }
+
+        histogram.retain(|_, n| *n != 0);
+        histogram
+    }
+
+    fn count_at_least(&self, k: usize) -> usize {
+        self.histogram().range(k.max(1)..).map(|(_, n)| n).sum()
+    }
+
+    /// The `n` points covered by the most vents, most dangerous first.
+    /// Points that are equally dangerous are in no particular order.
+    fn most_dangerous(&self, n: usize) -> Vec<(Coord, usize)> {
+        let mut crossings: Vec<_> = self
+            .crossings
+            .iter()
+            .map(|(&coord, depths)| (coord, depths.values().sum::<usize>()))
+            .collect();
+        crossings.sort_by_key(|&(_, overlaps)| Reverse(overlaps));
+        let mut crossings = crossings.into_iter().peekable();
+
+        let mut pieces: Vec<_> = self
+            .lines
+            .iter()
+            .flat_map(|l| l.pieces.iter().map(move |p| (l, p)))
+            .collect();
+        pieces.sort_by_key(|(_, p)| Reverse(p.depth));
+
+        let mut points = vec![];
+
+        for (line, piece) in pieces {
+            while let Some(crossing) = crossings.next_if(|&(_, overlaps)| overlaps >= piece.depth) {
+                points.push(crossing);
+            }
+
+            let rest = line
+                .points(piece)
+                .filter(|coord| !self.crossings.contains_key(coord))
+                .map(|coord| (coord, piece.depth));
+            points.extend(rest.take(n.saturating_sub(points.len())));
+
+            if points.len() >= n {
+                break;
+            }
+        }
+        points.extend(crossings);
+
+        points.truncate(n);
+        points
+    }
+
+    /// The overlap count of every point in `crop`, or in the smallest
+    /// region holding every vent. `None` when that region has more than
+    /// `MAX_HEATMAP_POINTS`.
+    fn heatmap(&self, crop: Option<Region>) -> Option<Heatmap> {
+        let region = crop.unwrap_or_else(|| self.bounds());
+        if region.n_points().is_none_or(|n| n > MAX_HEATMAP_POINTS) {
+            return None;
+        }
+
+        let mut heatmap = Heatmap {
+            region,
+            counts: vec![0; region.width() * region.height()],
+        };
+
+        // Every line adds its own coverage, so crossings add up correctly
+        for line in &self.lines {
+            for piece in &line.pieces {
+                for coord in line.points_within(piece, &region) {
+                    *heatmap.count_mut(coord) += piece.depth;
+                }
+            }
+        }
+
+        Some(heatmap)
+    }
+
+    fn bounds(&self) -> Region {
+        let ends = self.lines.iter().flat_map(|l| {
+            l.pieces.iter().flat_map(|p| {
+                let (x, y) = l.first_point(p);
+                let n = l.len(p) as i64 - 1;
+                [(x, y), (x + n * l.direction.dx, y + n * l.direction.dy)]
+            })
+        });
+
+        let (xs, ys): (Vec<_>, Vec<_>) = ends.unzip();
+        match (
+            xs.iter().minmax().into_option(),
+            ys.iter().minmax().into_option(),
+        ) {
+            (Some((&x_min, &x_max)), Some((&y_min, &y_max))) => Region {
+                min: (x_min, y_min),
+                max: (x_max, y_max),
+            },
+            _ => Region {
+                min: (0, 0),
+                max: (-1, -1),
+            },
+        }
+    }
+}
+
+/// Bigger heatmaps would not fit in memory.
+const MAX_HEATMAP_POINTS: usize = 1 << 28;
+
+/// The points from `min` to `max`, inclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Region {
+    min: Coord,
+    max: Coord,
+}
+
+impl Region {
+    fn width(&self) -> usize {
+        (self.max.0 - self.min.0 + 1).max(0) as usize
+    }
+
+    fn height(&self) -> usize {
+        (self.max.1 - self.min.1 + 1).max(0) as usize
+    }
+
+    fn n_points(&self) -> Option<usize> {
+        self.width().checked_mul(self.height())
+    }
+}
+
+#[derive(Debug)]
+struct Heatmap {
+    region: Region,
+    /// Row-major, starting at `region.min`
+    counts: Vec<usize>,
+}
+
+impl Heatmap {
+    fn count_mut(&mut self, (x, y): Coord) -> &mut usize {
+        let x = (x - self.region.min.0) as usize;
+        let y = (y - self.region.min.1) as usize;
+        &mut self.counts[y * self.region.width() + x]
+    }
+
+    fn pixels<'a>(&'a self, ramp: &'a ColorRamp) -> impl Iterator<Item = [u8; 3]> + 'a {
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        self.counts.iter().map(move |&c| ramp.color(c, max))
+    }
+
+    /// A binary greyscale image, using the brightness of each colour.
+    fn write_pgm(&self, ramp: &ColorRamp, mut w: impl Write) -> io::Result<()> {
+        let (width, height) = (self.region.width(), self.region.height());
+        write!(w, "P5\n{} {}\n255\n", width, height)?;
+
+        let luma: Vec<_> = self
+            .pixels(ramp)
+            .map(|[r, g, b]| {
+                ((299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000) as u8
+            })
+            .collect();
+        w.write_all(&luma)
+    }
+
+    fn write_png(&self, ramp: &ColorRamp, w: impl Write) -> io::Result<()> {
+        let (width, height) = (self.region.width(), self.region.height());
+
+        let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<_> = self.pixels(ramp).flatten().collect();
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)
+    }
+}
+
+/// Colours evenly spread from no overlaps to the most overlaps, with
+/// linear interpolation in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ColorRamp {
+    colors: Vec<[u8; 3]>,
+}
+
+impl ColorRamp {
+    fn grayscale() -> Self {
+        Self {
+            colors: vec![[0, 0, 0], [255, 255, 255]],
+        }
+    }
+
+    /// Black through red and yellow to white.
+    fn heat() -> Self {
+        Self {
+            colors: vec![[0, 0, 0], [255, 0, 0], [255, 255, 0], [255, 255, 255]],
+        }
+    }
+
+    fn color(&self, count: usize, max: usize) -> [u8; 3] {
+        let last = self.colors.len() - 1;
+        if max == 0 || last == 0 {
+            return self.colors[0];
+        }
+
+        // Fixed point, with 8 bits of fraction
+        let position = count.min(max) * last * 256 / max;
+        let (idx, frac) = (position / 256, position % 256);
+        if idx == last {
+            return self.colors[last];
+        }
+
+        let (a, b) = (self.colors[idx], self.colors[idx + 1]);
+        let mut color = [0; 3];
+        for ((c, a), b) in color.iter_mut().zip(a).zip(b) {
+            *c = ((usize::from(a) * (256 - frac) + usize::from(b) * frac) / 256) as u8;
+        }
+        color
+    }
+}
+
+fn parse_segments(s: &str) -> impl Iterator<Item = Segment> + '_ {
+    s.lines().flat_map(|l| {
         let (l, r) = l.split_once("->")?;
 
-        let (x1, y1) = l.trim().split_once(",")?;
-        let (x2, y2) = r.trim().split_once(",")?;
-
-        let x1 = x1.parse().ok()?;
-        let y1 = y1.parse().ok()?;
-        let x2 = x2.parse().ok()?;
-        let y2 = y2.parse().ok()?;
-
-        Some(((x1, y1), (x2, y2)))
-    });
-
-    let mut grid = Grid::default();
-    for ((x1, y1), (x2, y2)) in lines {
-        let mut vertical;
-        let mut horizontal;
-        let mut diagonal;
-        let mut diagonal_null;
-
-        let coords: &mut dyn Iterator<Item = Coord> = if x1 == x2 {
-            let xs = iter::repeat(x1);
-            let ys = increasing_range_inclusive(y1, y2);
-            vertical = xs.zip(ys);
-
-            &mut vertical
-        } else if y1 == y2 {
-            let xs = increasing_range_inclusive(x1, x2);
-            let ys = iter::repeat(y1);
-            horizontal = xs.zip(ys);
-
-            &mut horizontal
-        } else if include_diagonals {
-            let xs = increasing_range_inclusive(x1, x2);
-            let ys = increasing_range_inclusive(y1, y2);
-            diagonal = xs.zip(ys);
-
-            &mut diagonal
+        Some(Segment {
+            from: parse_coord(l)?,
+            to: parse_coord(r)?,
+        })
+    })
+}
+
+fn parse_coord(s: &str) -> Option<Coord> {
+    let (x, y) = s.trim().split_once(",")?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
+
+/// Parses `x1,y1,x2,y2`.
+fn parse_region(s: &str) -> Option<Region> {
+    let (x1, y1, x2, y2) = s.split(',').map(|v| v.trim().parse()).collect_tuple()?;
+    Some(Region {
+        min: (x1.ok()?, y1.ok()?),
+        max: (x2.ok()?, y2.ok()?),
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Segment {
+    from: Coord,
+    to: Coord,
+}
+
+impl Segment {
+    fn class(&self) -> LineClass {
+        let dx = self.to.0 - self.from.0;
+        let dy = self.to.1 - self.from.1;
+
+        // A single point is treated as a vertical line
+        if dx == 0 {
+            LineClass::Vertical
+        } else if dy == 0 {
+            LineClass::Horizontal
+        } else if dx.abs() == dy.abs() {
+            LineClass::Diagonal
         } else {
-            diagonal_null = iter::empty();
+            LineClass::Other
+        }
+    }
 
-            &mut diagonal_null
-        };
+    fn direction(&self) -> Direction {
+        let dx = self.to.0 - self.from.0;
+        let dy = self.to.1 - self.from.1;
 
-        for coord in coords {
-            *grid.entry(coord).or_default() += 1;
+        match gcd(dx.abs(), dy.abs()) {
+            0 => Direction::new(0, 1),
+            n => Direction::new(dx / n, dy / n),
         }
     }
 
-    // print_grid(&grid);
-    grid.values().filter(|&&c| c >= 2).count()
+    /// Every lattice point the segment passes through, from one end to
+    /// the other.
+    #[cfg(test)]
+    fn points(&self) -> impl Iterator<Item = Coord> {
+        let Direction { dx, dy } = self.direction();
+        let Segment { from, to } = *self;
+
+        let (first, last) = if from <= to { (from, to) } else { (to, from) };
+        let n = if dx != 0 {
+            (last.0 - first.0) / dx
+        } else {
+            (last.1 - first.1) / dy
+        };
+
+        (0..=n).map(move |i| (first.0 + i * dx, first.1 + i * dy))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LineClass {
+    Horizontal,
+    Vertical,
+    /// At exactly 45°
+    Diagonal,
+    /// Any other slope
+    Other,
 }
 
-fn increasing_range_inclusive<'a, T>(a: T, b: T) -> impl Iterator<Item = T> + 'a
-where
-    T: 'a,
-    T: PartialOrd,
-    RangeInclusive<T>: DoubleEndedIterator<Item = T>,
-{
-    if a < b {
-        Box::new(a..=b) as Box<dyn Iterator<Item = T>>
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
     } else {
-        Box::new((b..=a).rev())
+        gcd(b, a % b)
     }
 }
 
-#[allow(unused)]
-fn print_grid(grid: &Grid) {
-    let (x_min, x_max) = grid.keys().map(|(x, _)| *x).minmax().into_option().unwrap();
-    let (y_min, y_max) = grid.keys().map(|(_, y)| *y).minmax().into_option().unwrap();
+/// The step between neighbouring lattice points on a line, normalized so
+/// that parallel lines share a direction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Direction {
+    dx: i64,
+    dy: i64,
+}
+
+impl Direction {
+    fn new(dx: i64, dy: i64) -> Self {
+        if dx < 0 || (dx == 0 && dy < 0) {
+            Self { dx: -dx, dy: -dy }
+        } else {
+            Self { dx, dy }
+        }
+    }
 
-    for y in y_min..=y_max {
-        for x in x_min..=x_max {
-            match grid.get(&(x, y)) {
-                Some(v) => print!("{:02} ", v),
-                None => print!(".. "),
+    /// Identifies which of the parallel lines a point is on.
+    fn offset(&self, (x, y): Coord) -> i64 {
+        self.dy * x - self.dx * y
+    }
+
+    /// How far along its line a point is.
+    fn position(&self, (x, y): Coord) -> i64 {
+        self.dx * x + self.dy * y
+    }
+
+    /// The difference in position between neighbouring lattice points.
+    fn step(&self) -> i64 {
+        self.dx * self.dx + self.dy * self.dy
+    }
+}
+
+/// Every vent lying along one infinite line, swept into pieces of
+/// constant coverage.
+#[derive(Debug)]
+struct Line {
+    direction: Direction,
+    offset: i64,
+    pieces: Vec<Piece>,
+}
+
+/// The positions `start..=end` along a line, all covered by `depth` vents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Piece {
+    start: i64,
+    end: i64,
+    depth: usize,
+}
+
+impl Line {
+    fn new(direction: Direction, offset: i64, spans: &[(i64, i64)]) -> Self {
+        let step = direction.step();
+
+        let mut events: Vec<_> = spans
+            .iter()
+            .flat_map(|&(start, end)| [(start, 1), (end + step, -1)])
+            .collect();
+        events.sort_unstable();
+
+        let mut pieces = vec![];
+        let mut depth = 0i64;
+        let mut previous = None;
+
+        for (position, delta) in events {
+            if let Some(start) = previous {
+                if position > start && depth > 0 {
+                    pieces.push(Piece {
+                        start,
+                        end: position - step,
+                        depth: depth as usize,
+                    });
+                }
             }
+
+            depth += delta;
+            previous = Some(position);
         }
-        println!();
+
+        Self {
+            direction,
+            offset,
+            pieces,
+        }
+    }
+
+    /// The number of lattice points in a piece.
+    fn len(&self, piece: &Piece) -> usize {
+        ((piece.end - piece.start) / self.direction.step() + 1) as usize
+    }
+
+    /// How many vents cover a point on this line.
+    fn depth_at(&self, coord: Coord) -> Option<usize> {
+        if self.direction.offset(coord) != self.offset {
+            return None;
+        }
+
+        let position = self.direction.position(coord);
+        let idx = self.pieces.partition_point(|p| p.start <= position);
+        let piece = self.pieces[..idx].last()?;
+        (position <= piece.end).then_some(piece.depth)
+    }
+
+    fn first_point(&self, piece: &Piece) -> Coord {
+        let Direction { dx, dy } = self.direction;
+        let (offset, step) = (self.offset, self.direction.step());
+
+        // Invert `offset` and `position`
+        let x = (dy * offset + dx * piece.start) / step;
+        let y = (dy * piece.start - dx * offset) / step;
+        (x, y)
+    }
+
+    /// Every lattice point in a piece of this line.
+    fn points(&self, piece: &Piece) -> impl Iterator<Item = Coord> {
+        let Direction { dx, dy } = self.direction;
+        let (x, y) = self.first_point(piece);
+
+        (0..self.len(piece) as i64).map(move |i| (x + i * dx, y + i * dy))
+    }
+
+    /// Every lattice point in a piece of this line that is also inside a
+    /// region, without stepping over the points outside of it.
+    fn points_within(&self, piece: &Piece, region: &Region) -> impl Iterator<Item = Coord> {
+        let Direction { dx, dy } = self.direction;
+        let (x, y) = self.first_point(piece);
+
+        let mut lo = 0;
+        let mut hi = self.len(piece) as i64 - 1;
+
+        let axes = [
+            (x, dx, region.min.0, region.max.0),
+            (y, dy, region.min.1, region.max.1),
+        ];
+        for (start, d, min, max) in axes {
+            // Keep the steps `i` where `min <= start + i * d <= max`
+            let (start, d, min, max) = match d.cmp(&0) {
+                Ordering::Equal => {
+                    if !(min..=max).contains(&start) {
+                        hi = -1;
+                    }
+                    continue;
+                }
+                Ordering::Greater => (start, d, min, max),
+                Ordering::Less => (-start, -d, -max, -min),
+            };
+
+            lo = lo.max(-(start - min).div_euclid(d));
+            hi = hi.min((max - start).div_euclid(d));
+        }
+
+        (lo..=hi).map(move |i| (x + i * dx, y + i * dy))
+    }
+
+    /// The lattice point where two non-parallel lines meet, if any.
+    fn intersection(&self, other: &Line) -> Option<Coord> {
+        // Each line is `a * x + b * y = c`
+        let (a1, b1, c1) = self.equation();
+        let (a2, b2, c2) = other.equation();
+
+        let det = a1 * b2 - a2 * b1;
+        if det == 0 {
+            return None;
+        }
+
+        let x = c1 * b2 - c2 * b1;
+        let y = a1 * c2 - a2 * c1;
+        if x % det != 0 || y % det != 0 {
+            return None;
+        }
+
+        Some(((x / det) as i64, (y / det) as i64))
+    }
+
+    fn equation(&self) -> (i128, i128, i128) {
+        let Direction { dx, dy } = self.direction;
+        (dy.into(), (-dx).into(), self.offset.into())
     }
 }
 
+fn merge_collinear(segments: impl IntoIterator<Item = Segment>) -> Vec<Line> {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+
+    for segment in segments {
+        let direction = segment.direction();
+        let offset = direction.offset(segment.from);
+        let a = direction.position(segment.from);
+        let b = direction.position(segment.to);
+        groups
+            .entry((direction, offset))
+            .or_default()
+            .push((a.min(b), a.max(b)));
+    }
+
+    groups
+        .into_iter()
+        .map(|((direction, offset), spans)| Line::new(direction, offset, &spans))
+        .collect()
+}
+
+/// Every point covered by more than one line, with the coverage from
+/// each of those lines (keyed by index).
+fn crossings(lines: &[Line]) -> BTreeMap<Coord, BTreeMap<usize, usize>> {
+    let mut crossings = BTreeMap::<_, BTreeMap<_, _>>::new();
+
+    for (i, a) in lines.iter().enumerate() {
+        for (j, b) in lines.iter().enumerate().skip(i + 1) {
+            let coord = match a.intersection(b) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            if let (Some(depth_a), Some(depth_b)) = (a.depth_at(coord), b.depth_at(coord)) {
+                let depths = crossings.entry(coord).or_default();
+                depths.insert(i, depth_a);
+                depths.insert(j, depth_b);
+            }
+        }
+    }
+
+    crossings
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     const TEST_INPUT: &str = include_str!("../test-input");
 
+    type Grid = BTreeMap<Coord, usize>;
+
     #[test]
     fn test_part_1() {
         assert_eq!(5, number_overlapping_points(TEST_INPUT, false));
@@ -110,4 +726,186 @@ mod test {
     fn test_part_2() {
         assert_eq!(12, number_overlapping_points(TEST_INPUT, true));
     }
+
+    fn rasterize(s: &str, classes: &[LineClass]) -> Grid {
+        let mut grid = Grid::new();
+
+        for segment in parse_segments(s).filter(|segment| classes.contains(&segment.class())) {
+            for coord in segment.points() {
+                *grid.entry(coord).or_default() += 1;
+            }
+        }
+
+        grid
+    }
+
+    #[test]
+    fn test_matches_rasterizing() {
+        let cases = [
+            TEST_INPUT,
+            // Collinear runs that overlap, touch end to end and run
+            // backwards
+            "0,0 -> 5,0\n3,0 -> 9,0\n10,0 -> 9,0\n2,0 -> 2,0\n4,4 -> 4,-2\n4,1 -> 4,3\n",
+            // Diagonals both ways, crossing each other and the axes
+            "0,0 -> 8,8\n8,0 -> 0,8\n2,2 -> 6,6\n0,4 -> 8,4\n4,0 -> 4,8\n7,1 -> 3,5\n",
+            // Other slopes, crossing on and between grid points
+            "0,0 -> 6,3\n0,3 -> 6,0\n2,1 -> 4,2\n0,0 -> 3,6\n1,5 -> 1,0\n5,5 -> 0,0\n",
+            // A star of every direction through one point
+            "0,5 -> 10,5\n5,0 -> 5,10\n0,0 -> 10,10\n0,10 -> 10,0\n1,3 -> 9,7\n3,9 -> 7,1\n",
+        ];
+
+        for input in cases {
+            check_matches_rasterizing(input);
+        }
+    }
+
+    fn check_matches_rasterizing(input: &str) {
+        use LineClass::*;
+        for classes in [
+            &[Horizontal, Vertical][..],
+            &[Horizontal, Vertical, Diagonal],
+            &[Horizontal, Vertical, Diagonal, Other],
+            &[Other],
+        ] {
+            let grid = rasterize(input, classes);
+            let field = VentField::parse(input, classes);
+
+            assert_eq!(
+                grid.values().filter(|&&c| c >= 2).count(),
+                number_overlapping_points_with(input, classes),
+                "{classes:?} for {input}"
+            );
+            assert_eq!(
+                grid.values().copied().counts(),
+                field.histogram().into_iter().collect()
+            );
+            for (&coord, &n) in &grid {
+                assert_eq!(n, field.overlaps_at(coord), "At {coord:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_huge_coordinates() {
+        let input = r#"
+0,1000000 -> 2000000,1000000
+1000000,0 -> 1000000,2000000
+3000000,1000000 -> 1500000,1000000
+0,0 -> 3000000,3000000
+"#;
+
+        // The horizontal overlap, plus the crossing of the vertical and
+        // diagonal lines with the horizontal one (at the same point)
+        assert_eq!(500_001 + 1, number_overlapping_points(input, true));
+
+        // Far too big to draw whole, but a crop is fine
+        use LineClass::*;
+        let field = VentField::parse(input, &[Horizontal, Vertical, Diagonal]);
+        assert!(field.heatmap(None).is_none());
+        let crop = Region {
+            min: (999_999, 999_999),
+            max: (1_000_001, 1_000_001),
+        };
+        let heatmap = field.heatmap(Some(crop)).unwrap();
+        assert_eq!([1, 1, 0, 1, 3, 1, 0, 1, 1], &*heatmap.counts);
+    }
+
+    #[test]
+    fn test_arbitrary_slopes() {
+        let segment = Segment {
+            from: (6, 5),
+            to: (0, 1),
+        };
+        assert_eq!(LineClass::Other, segment.class());
+        assert_eq!(
+            vec![(0, 1), (3, 3), (6, 5)],
+            segment.points().collect::<Vec<_>>()
+        );
+
+        // Crosses at (3, 3), and at (4.5, 4) which is not a lattice point
+        let input = r#"
+0,1 -> 6,5
+0,3 -> 6,3
+0,4 -> 6,4
+"#;
+        assert_eq!(
+            1,
+            number_overlapping_points_with(input, &[LineClass::Horizontal, LineClass::Other])
+        );
+    }
+
+    #[test]
+    fn test_vent_field_queries() {
+        let field = VentField::parse(TEST_INPUT, &[LineClass::Horizontal, LineClass::Vertical]);
+
+        assert_eq!(BTreeMap::from([(1, 16), (2, 5)]), field.histogram());
+        assert_eq!(21, field.count_at_least(1));
+        assert_eq!(0, field.count_at_least(3));
+
+        assert_eq!(2, field.overlaps_at((0, 9)));
+        assert_eq!(1, field.overlaps_at((7, 2)));
+        assert_eq!(0, field.overlaps_at((0, 0)));
+
+        let dangerous = field.most_dangerous(6);
+        assert_eq!(6, dangerous.len());
+        assert!(dangerous[..5].iter().all(|&(_, n)| n == 2));
+        assert_eq!(1, dangerous[5].1);
+        for (coord, n) in dangerous {
+            assert_eq!(n, field.overlaps_at(coord));
+        }
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let field = VentField::parse(TEST_INPUT, &[LineClass::Horizontal, LineClass::Vertical]);
+        let grid = rasterize(TEST_INPUT, &[LineClass::Horizontal, LineClass::Vertical]);
+
+        let heatmap = field.heatmap(None).unwrap();
+        assert_eq!(
+            Region {
+                min: (0, 0),
+                max: (9, 9)
+            },
+            heatmap.region
+        );
+        assert_eq!(grid.values().sum::<usize>(), heatmap.counts.iter().sum());
+
+        let crop = Region {
+            min: (1, 3),
+            max: (4, 9),
+        };
+        assert_eq!(Some(crop), parse_region("1,3,4,9"));
+        let heatmap = field.heatmap(Some(crop)).unwrap();
+        assert_eq!(4 * 7, heatmap.counts.len());
+        for (i, &count) in heatmap.counts.iter().enumerate() {
+            let coord = (1 + (i % 4) as i64, 3 + (i / 4) as i64);
+            assert_eq!(
+                grid.get(&coord).copied().unwrap_or(0),
+                count,
+                "At {coord:?}"
+            );
+        }
+
+        let mut pgm = vec![];
+        heatmap
+            .write_pgm(&ColorRamp::grayscale(), &mut pgm)
+            .unwrap();
+        assert!(pgm.starts_with(b"P5\n4 7\n255\n"));
+        assert_eq!(b"P5\n4 7\n255\n".len() + 4 * 7, pgm.len());
+
+        let mut png = vec![];
+        heatmap.write_png(&ColorRamp::heat(), &mut png).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_color_ramp() {
+        let ramp = ColorRamp::heat();
+
+        assert_eq!([0, 0, 0], ramp.color(0, 6));
+        assert_eq!([255, 0, 0], ramp.color(2, 6));
+        assert_eq!([255, 127, 0], ramp.color(3, 6));
+        assert_eq!([255, 255, 255], ramp.color(6, 6));
+        assert_eq!([0, 0, 0], ramp.color(0, 0));
+    }
 }
@@ -1,28 +1,541 @@
+use num_bigint::BigUint;
+use snafu::{ensure, ResultExt, Snafu};
+use std::{
+    env, fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    num::ParseIntError,
+    str::FromStr,
+};
+
 const INPUT: &str = include_str!("../input");
 
 fn main() {
     // Wrong: 385449 (too low) -- last number wasn't being parsed
     println!("part1: {}", simulate_lanternfish(INPUT, 80));
     println!("part2: {}", simulate_lanternfish(INPUT, 256));
+
+    let mut lifecycle = Lifecycle::STANDARD;
+    let mut n_days = None;
+    let mut modulus = None;
+    let mut csv_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--days" => n_days = Some(option_value(&mut args, &arg)),
+            "--lifespan" => lifecycle.limit = Some(Limit::Lifespan(option_value(&mut args, &arg))),
+            "--spawns" => lifecycle.limit = Some(Limit::Spawns(option_value(&mut args, &arg))),
+            "--modulo" => modulus = Some(option_value(&mut args, &arg)),
+            // Writes every simulated day to this file
+            "--csv" => csv_path = Some(args.next().expect("--csv needs a path")),
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+
+    let custom = lifecycle != Lifecycle::STANDARD || n_days.is_some() || modulus.is_some();
+    let n_days = n_days.unwrap_or(256);
+
+    if custom {
+        match modulus {
+            Some(modulus) => {
+                let total = simulate_lanternfish_modulo(INPUT, n_days, &lifecycle, modulus);
+                println!("{n_days} days: {} (mod {modulus})", total.unwrap());
+            }
+            None => {
+                let total = simulate_lanternfish_with(INPUT, n_days, &lifecycle, &Exact);
+                println!("{n_days} days: {}", total.unwrap());
+            }
+        }
+    }
+
+    if let Some(path) = csv_path {
+        let history = lanternfish_history(INPUT, &lifecycle, &Exact).unwrap();
+        let n_days = usize::try_from(n_days).expect("Too many days to write");
+        let mut file = BufWriter::new(File::create(path).expect("Unable to create CSV"));
+        write_csv(history.take(n_days + 1), &mut file).expect("Unable to write CSV");
+        file.flush().expect("Unable to write CSV");
+    }
+}
+
+fn option_value<T: FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> T {
+    args.next()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| panic!("{option} needs a number"))
 }
 
 fn simulate_lanternfish(s: &str, n_days: usize) -> usize {
-    let mut days = [0; 9];
+    let total = simulate_lanternfish_exact(s, n_days as u64);
+    usize::try_from(&total).expect("Too many fish for a usize")
+}
+
+/// The number of fish, modulo `modulus`. This is cheap even for an
+/// astronomical number of days.
+fn simulate_lanternfish_modulo(
+    s: &str,
+    n_days: u64,
+    lifecycle: &Lifecycle,
+    modulus: u64,
+) -> Result<u64> {
+    ensure!(modulus > 0, ZeroModulusSnafu);
+    simulate_lanternfish_with(s, n_days, lifecycle, &Modulo(modulus))
+}
+
+/// The exact number of fish. The answer has roughly `n_days / 27`
+/// digits, so this is only practical up to millions of days.
+fn simulate_lanternfish_exact(s: &str, n_days: u64) -> BigUint {
+    simulate_lanternfish_with(s, n_days, &Lifecycle::STANDARD, &Exact).unwrap()
+}
+
+/// How a fish ages and reproduces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Lifecycle {
+    /// The timer of a fish that has just made a new fish
+    reset_timer: usize,
+    /// The timer of a newly made fish
+    newborn_timer: usize,
+    limit: Option<Limit>,
+}
+
+/// When a fish dies. Fish in the initial population are treated as
+/// newborn.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Limit {
+    /// After this many days
+    Lifespan(usize),
+    /// After making this many new fish
+    Spawns(usize),
+}
+
+impl Lifecycle {
+    const STANDARD: Self = Self {
+        reset_timer: 6,
+        newborn_timer: 8,
+        limit: None,
+    };
+
+    fn max_timer(&self) -> usize {
+        self.reset_timer.max(self.newborn_timer)
+    }
+
+    fn n_ages(&self) -> usize {
+        match self.limit {
+            Some(Limit::Lifespan(days)) => days,
+            _ => 1,
+        }
+    }
+
+    fn n_spawns(&self) -> usize {
+        match self.limit {
+            Some(Limit::Spawns(spawns)) => spawns,
+            _ => 1,
+        }
+    }
+
+    fn n_states(&self) -> usize {
+        (self.max_timer() + 1) * self.n_ages() * self.n_spawns()
+    }
+
+    fn state(&self, s: State) -> usize {
+        let n_timers = self.max_timer() + 1;
+        (s.spawns * self.n_ages() + s.age) * n_timers + s.timer
+    }
+
+    fn states(&self) -> impl Iterator<Item = State> + '_ {
+        let spawns = 0..self.n_spawns();
+        spawns.flat_map(move |spawns| {
+            (0..self.n_ages()).flat_map(move |age| {
+                (0..=self.max_timer()).map(move |timer| State { timer, age, spawns })
+            })
+        })
+    }
+
+    /// Where a fish is the next day: itself (unless it died) and
+    /// possibly a new fish.
+    fn next(&self, s: State) -> impl Iterator<Item = State> {
+        let (tracks_age, tracks_spawns) = match self.limit {
+            Some(Limit::Lifespan(_)) => (1, 0),
+            Some(Limit::Spawns(_)) => (0, 1),
+            None => (0, 0),
+        };
+        let age = s.age + tracks_age;
+        let alive = age < self.n_ages();
+
+        let (parent, child) = if s.timer == 0 {
+            let spawns = s.spawns + tracks_spawns;
+            let parent = State {
+                timer: self.reset_timer,
+                age,
+                spawns,
+            };
+            let child = State {
+                timer: self.newborn_timer,
+                age: 0,
+                spawns: 0,
+            };
+            let alive = alive && spawns < self.n_spawns();
+            (alive.then_some(parent), Some(child))
+        } else {
+            let parent = State {
+                timer: s.timer - 1,
+                age,
+                spawns: s.spawns,
+            };
+            (alive.then_some(parent), None)
+        };
+
+        parent.into_iter().chain(child)
+    }
+}
+
+impl Lifecycle {
+    /// Every `(from, to)` pair of state indices that fish move between
+    /// each day. A fish that reproduces appears twice.
+    fn transitions(&self) -> Vec<(usize, usize)> {
+        self.states()
+            .flat_map(|from| self.next(from).map(move |to| (from, to)))
+            .map(|(from, to)| (self.state(from), self.state(to)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct State {
+    timer: usize,
+    age: usize,
+    spawns: usize,
+}
+
+/// The number of fish in each state, after validating the lifecycle.
+fn initial_population(s: &str, lifecycle: &Lifecycle) -> Result<Vec<u64>> {
+    ensure!(
+        lifecycle.n_ages() > 0 && lifecycle.n_spawns() > 0,
+        ZeroLimitSnafu {
+            limit: lifecycle.limit
+        }
+    );
+
+    let mut population = vec![0; lifecycle.n_states()];
+
+    for timer in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let timer: usize = timer.parse().context(InvalidTimerSnafu { timer })?;
+        let max = lifecycle.max_timer();
+        ensure!(timer <= max, TimerOutOfRangeSnafu { timer, max });
+
+        population[lifecycle.state(State {
+            timer,
+            age: 0,
+            spawns: 0,
+        })] += 1;
+    }
+
+    Ok(population)
+}
+
+/// Every day of the simulation, starting with the initial population.
+/// This never ends, so take as many days as are needed.
+fn lanternfish_history<'a, A: Arithmetic>(
+    s: &str,
+    lifecycle: &Lifecycle,
+    arith: &'a A,
+) -> Result<History<'a, A>> {
+    let population = initial_population(s, lifecycle)?;
+
+    Ok(History {
+        arith,
+        timers: lifecycle.states().map(|s| s.timer).collect(),
+        n_timers: lifecycle.max_timer() + 1,
+        transitions: lifecycle.transitions(),
+        population: population.into_iter().map(|c| arith.value(c)).collect(),
+        day: 0,
+    })
+}
+
+struct History<'a, A: Arithmetic> {
+    arith: &'a A,
+    /// The timer for each state
+    timers: Vec<usize>,
+    n_timers: usize,
+    transitions: Vec<(usize, usize)>,
+    population: Vec<A::Value>,
+    day: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Day<V> {
+    day: u64,
+    total: V,
+    /// The number of fish with each timer value
+    timers: Vec<V>,
+}
+
+impl<A: Arithmetic> Iterator for History<'_, A> {
+    type Item = Day<A::Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let arith = self.arith;
+
+        let mut timers = vec![arith.zero(); self.n_timers];
+        for (&timer, count) in self.timers.iter().zip(&self.population) {
+            timers[timer] = arith.add(&timers[timer], count);
+        }
+        let total = timers
+            .iter()
+            .fold(arith.zero(), |acc, c| arith.add(&acc, c));
+        let today = Day {
+            day: self.day,
+            total,
+            timers,
+        };
+
+        self.population = step(&self.population, &self.transitions, arith);
+        self.day += 1;
+
+        Some(today)
+    }
+}
+
+fn write_csv<V: fmt::Display>(
+    days: impl IntoIterator<Item = Day<V>>,
+    mut w: impl Write,
+) -> io::Result<()> {
+    let mut days = days.into_iter().peekable();
+
+    let n_timers = days.peek().map_or(0, |d| d.timers.len());
+    write!(w, "day,total")?;
+    for timer in 0..n_timers {
+        write!(w, ",timer_{}", timer)?;
+    }
+    writeln!(w)?;
+
+    for day in days {
+        write!(w, "{},{}", day.day, day.total)?;
+        for count in &day.timers {
+            write!(w, ",{}", count)?;
+        }
+        writeln!(w)?;
+    }
+
+    Ok(())
+}
+
+/// The number of fish in each state on the next day.
+fn step<A: Arithmetic>(
+    population: &[A::Value],
+    transitions: &[(usize, usize)],
+    arith: &A,
+) -> Vec<A::Value> {
+    let mut tomorrow = vec![arith.zero(); population.len()];
+    for &(from, to) in transitions {
+        tomorrow[to] = arith.add(&tomorrow[to], &population[from]);
+    }
+    tomorrow
+}
+
+type Matrix<V> = Vec<Vec<V>>;
+
+/// Each day is the same linear transformation of the number of fish in
+/// each state. With `S` states, applying it once a day costs
+/// `O(n_days * S)` and raising it to the `n_days`th power by repeated
+/// squaring costs `O(S³ log n_days)`, so whichever is cheaper is used.
+/// A limit multiplies the number of states: `Lifespan(60)` has 540.
+fn simulate_lanternfish_with<A: Arithmetic>(
+    s: &str,
+    n_days: u64,
+    lifecycle: &Lifecycle,
+    arith: &A,
+) -> Result<A::Value> {
+    let population = initial_population(s, lifecycle)?;
+    let transitions = lifecycle.transitions();
+
+    let n_states = population.len() as u64;
+    let n_squarings = u64::from(u64::BITS - n_days.leading_zeros());
+    let squaring_cost = n_states.saturating_pow(3).saturating_mul(2 * n_squarings);
+    let stepping_cost = n_days.saturating_mul(transitions.len() as u64);
 
-    for timer in s.split(',').flat_map(|d| d.trim().parse::<usize>()) {
-        days[timer] += 1;
+    if stepping_cost <= squaring_cost {
+        Ok(simulate_by_stepping(
+            &population,
+            n_days,
+            &transitions,
+            arith,
+        ))
+    } else {
+        Ok(simulate_by_squaring(
+            &population,
+            n_days,
+            &transitions,
+            arith,
+        ))
     }
+}
 
+fn simulate_by_stepping<A: Arithmetic>(
+    population: &[u64],
+    n_days: u64,
+    transitions: &[(usize, usize)],
+    arith: &A,
+) -> A::Value {
+    let mut population: Vec<_> = population.iter().map(|&c| arith.value(c)).collect();
     for _ in 0..n_days {
-        let num_zero = days[0];
-        days.rotate_left(1);
-        days[6] += num_zero; // The fish continues
-        days[8] = num_zero; // And makes a new fish
+        population = step(&population, transitions, arith);
+    }
+
+    population
+        .iter()
+        .fold(arith.zero(), |acc, c| arith.add(&acc, c))
+}
+
+fn simulate_by_squaring<A: Arithmetic>(
+    population: &[u64],
+    n_days: u64,
+    transitions: &[(usize, usize)],
+    arith: &A,
+) -> A::Value {
+    let n_states = population.len();
+
+    let mut one_day = vec![vec![arith.zero(); n_states]; n_states];
+    for &(from, to) in transitions {
+        let cell = &mut one_day[to][from];
+        *cell = arith.add(cell, &arith.one());
+    }
+
+    let all_days = matrix_pow(&one_day, n_days, arith);
+
+    let mut total = arith.zero();
+    for row in &all_days {
+        for (cell, &count) in row.iter().zip(population) {
+            let fish = arith.mul(cell, &arith.value(count));
+            total = arith.add(&total, &fish);
+        }
+    }
+    total
+}
+
+fn matrix_pow<A: Arithmetic>(base: &Matrix<A::Value>, mut exp: u64, arith: &A) -> Matrix<A::Value> {
+    let n = base.len();
+    let mut result = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { arith.one() } else { arith.zero() })
+                .collect()
+        })
+        .collect();
+    let mut base = base.clone();
+
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = matrix_mul(&result, &base, arith);
+        }
+        base = matrix_mul(&base, &base, arith);
+        exp >>= 1;
     }
 
-    days.iter().sum()
+    result
+}
+
+fn matrix_mul<A: Arithmetic>(
+    a: &Matrix<A::Value>,
+    b: &Matrix<A::Value>,
+    arith: &A,
+) -> Matrix<A::Value> {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    (0..n).fold(arith.zero(), |acc, k| {
+                        arith.add(&acc, &arith.mul(&a[i][k], &b[k][j]))
+                    })
+                })
+                .collect()
+        })
+        .collect()
 }
 
+/// The operations needed to count fish, for a particular kind of number.
+trait Arithmetic {
+    type Value: Clone;
+
+    fn zero(&self) -> Self::Value;
+    fn one(&self) -> Self::Value;
+    fn value(&self, v: u64) -> Self::Value;
+    fn add(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+    fn mul(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+}
+
+/// Counting modulo some number, typically a large prime.
+struct Modulo(u64);
+
+impl Arithmetic for Modulo {
+    type Value = u64;
+
+    fn zero(&self) -> u64 {
+        0
+    }
+
+    fn one(&self) -> u64 {
+        1 % self.0
+    }
+
+    fn value(&self, v: u64) -> u64 {
+        v % self.0
+    }
+
+    fn add(&self, a: &u64, b: &u64) -> u64 {
+        ((u128::from(*a) + u128::from(*b)) % u128::from(self.0)) as u64
+    }
+
+    fn mul(&self, a: &u64, b: &u64) -> u64 {
+        ((u128::from(*a) * u128::from(*b)) % u128::from(self.0)) as u64
+    }
+}
+
+/// Counting with arbitrary precision.
+struct Exact;
+
+impl Arithmetic for Exact {
+    type Value = BigUint;
+
+    fn zero(&self) -> BigUint {
+        BigUint::from(0u8)
+    }
+
+    fn one(&self) -> BigUint {
+        BigUint::from(1u8)
+    }
+
+    fn value(&self, v: u64) -> BigUint {
+        BigUint::from(v)
+    }
+
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a + b
+    }
+
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a * b
+    }
+}
+
+#[derive(Debug, Snafu)]
+enum Error {
+    InvalidTimer {
+        timer: String,
+        source: ParseIntError,
+    },
+    TimerOutOfRange {
+        timer: usize,
+        max: usize,
+    },
+    ZeroLimit {
+        limit: Option<Limit>,
+    },
+    ZeroModulus,
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -43,4 +556,133 @@ mod test {
     fn test_part_2_256_days() {
         assert_eq!(26984457539, simulate_lanternfish(TEST_INPUT, 256));
     }
+
+    #[test]
+    fn test_modulo_matches_exact() {
+        const PRIME: u64 = 1_000_000_007;
+
+        for n_days in [0, 1, 256, 10_000] {
+            let exact = simulate_lanternfish_exact(TEST_INPUT, n_days) % PRIME;
+            let modulo =
+                simulate_lanternfish_modulo(TEST_INPUT, n_days, &Lifecycle::STANDARD, PRIME)
+                    .unwrap();
+            assert_eq!(exact, BigUint::from(modulo), "For {n_days} days");
+        }
+
+        let e = simulate_lanternfish_modulo(TEST_INPUT, 1, &Lifecycle::STANDARD, 0).unwrap_err();
+        assert!(matches!(e, Error::ZeroModulus));
+
+        // Too many fish to ever count exactly
+        let n_days = 10u64.pow(18);
+        assert!(
+            simulate_lanternfish_modulo(TEST_INPUT, n_days, &Lifecycle::STANDARD, PRIME).unwrap()
+                < PRIME
+        );
+    }
+
+    #[test]
+    fn test_custom_timers() {
+        // Reproducing every 2 days, starting immediately, doubles daily
+        let lifecycle = Lifecycle {
+            reset_timer: 0,
+            newborn_timer: 0,
+            limit: None,
+        };
+        let total = simulate_lanternfish_with("0", 10, &lifecycle, &Exact).unwrap();
+        assert_eq!(BigUint::from(1024u32), total);
+    }
+
+    #[test]
+    fn test_limits() {
+        let lifecycle = Lifecycle {
+            reset_timer: 0,
+            newborn_timer: 0,
+            limit: Some(Limit::Spawns(1)),
+        };
+        // Each fish is replaced by its child
+        let total = simulate_lanternfish_with("0,0", 50, &lifecycle, &Exact).unwrap();
+        assert_eq!(BigUint::from(2u32), total);
+
+        let lifecycle = Lifecycle {
+            limit: Some(Limit::Lifespan(3)),
+            ..Lifecycle::STANDARD
+        };
+        // The first fish spawns on day 1 and dies after day 3; its child
+        // never gets to spawn
+        let total = simulate_lanternfish_with("0", 2, &lifecycle, &Exact).unwrap();
+        assert_eq!(BigUint::from(2u32), total);
+        let total = simulate_lanternfish_with("0", 3, &lifecycle, &Exact).unwrap();
+        assert_eq!(BigUint::from(1u32), total);
+        let total = simulate_lanternfish_with("0", 20, &lifecycle, &Exact).unwrap();
+        assert_eq!(BigUint::from(0u32), total);
+    }
+
+    #[test]
+    fn test_stepping_matches_squaring() {
+        for limit in [None, Some(Limit::Lifespan(5)), Some(Limit::Spawns(2))] {
+            let lifecycle = Lifecycle {
+                limit,
+                ..Lifecycle::STANDARD
+            };
+            let population = initial_population(TEST_INPUT, &lifecycle).unwrap();
+            let transitions = lifecycle.transitions();
+
+            for n_days in [0, 1, 18, 80, 100] {
+                assert_eq!(
+                    simulate_by_squaring(&population, n_days, &transitions, &Exact),
+                    simulate_by_stepping(&population, n_days, &transitions, &Exact),
+                    "For {limit:?} after {n_days} days",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_timer_out_of_range() {
+        let e = simulate_lanternfish_with("3,9", 1, &Lifecycle::STANDARD, &Exact).unwrap_err();
+        assert!(matches!(e, Error::TimerOutOfRange { timer: 9, max: 8 }));
+
+        let e = simulate_lanternfish_with("3,x", 1, &Lifecycle::STANDARD, &Exact).unwrap_err();
+        assert!(matches!(e, Error::InvalidTimer { .. }));
+    }
+
+    #[test]
+    fn test_history() {
+        let history =
+            lanternfish_history(TEST_INPUT, &Lifecycle::STANDARD, &Modulo(u64::MAX)).unwrap();
+        let days: Vec<_> = history.take(81).collect();
+
+        assert_eq!(
+            Day {
+                day: 0,
+                total: 5,
+                timers: vec![0, 1, 1, 2, 1, 0, 0, 0, 0],
+            },
+            days[0]
+        );
+        assert_eq!(26, days[18].total);
+        assert_eq!(5934, days[80].total);
+
+        for day in &days {
+            let total =
+                simulate_lanternfish_modulo(TEST_INPUT, day.day, &Lifecycle::STANDARD, u64::MAX);
+            let total = total.unwrap();
+            assert_eq!(total, day.total, "On day {}", day.day);
+        }
+    }
+
+    #[test]
+    fn test_history_csv() {
+        let history = lanternfish_history(TEST_INPUT, &Lifecycle::STANDARD, &Exact).unwrap();
+
+        let mut csv = vec![];
+        write_csv(history.take(3), &mut csv).unwrap();
+        assert_eq!(
+            "day,total,timer_0,timer_1,timer_2,timer_3,timer_4,timer_5,timer_6,timer_7,timer_8\n\
+             0,5,0,1,1,2,1,0,0,0,0\n\
+             1,5,1,1,2,1,0,0,0,0,0\n\
+             2,6,1,2,1,0,0,0,1,0,1\n",
+            String::from_utf8(csv).unwrap(),
+        );
+    }
 }
@@ -1,66 +1,663 @@
-#![feature(int_abs_diff)]
-
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, env, ops::RangeInclusive};
 
 const INPUT: &str = include_str!("../input");
 
 fn main() {
-    println!("part1: {}", minimum_alignment_fuel(INPUT, fuel_cost_linear));
-    println!("part2: {}", minimum_alignment_fuel(INPUT, fuel_cost_ramped));
+    println!("part1: {}", minimum_alignment_fuel(INPUT, &Linear));
+    println!("part2: {}", minimum_alignment_fuel(INPUT, &Ramped));
+
+    let mut cost = None;
+    let mut cap = None;
+    let mut weights = BTreeMap::new();
+    let mut show_ties = false;
+    let mut show_per_crab = false;
+    let mut show_curve = false;
+    let mut rally = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--cost" => cost = Some(args.next().expect("--cost needs a value")),
+            "--cap" => {
+                let value = args.next().and_then(|c| c.parse().ok());
+                cap = Some(value.expect("--cap needs a number"));
+            }
+            // e.g. `--weight 16=10` makes each crab at 16 cost ten times as much
+            "--weight" => {
+                let value = args.next().expect("--weight needs a value");
+                let (pos, weight) = value.split_once('=').expect("--weight needs pos=weight");
+                weights.insert(pos.parse().unwrap(), weight.parse().unwrap());
+            }
+            "--ties" => show_ties = true,
+            "--per-crab" => show_per_crab = true,
+            // Prints `destination,fuel` lines for plotting
+            "--curve" => show_curve = true,
+            "--rally" => {
+                let value = args.next().and_then(|k| k.parse().ok());
+                rally = Some(value.expect("--rally needs a number"));
+            }
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+
+    let shown = show_ties || show_per_crab || show_curve || rally.is_some();
+    if cost.is_none() && cap.is_none() && weights.is_empty() && !shown {
+        return;
+    }
+
+    let mut fuel_cost: Box<dyn FuelCost> = match cost.as_deref().unwrap_or("ramped") {
+        "linear" => Box::new(Linear),
+        "ramped" => Box::new(Ramped),
+        "quadratic" => Box::new(Quadratic),
+        "cubic" => Box::new(PerDistance {
+            cost: |d: u64| d.pow(3),
+            convex: true,
+            nondecreasing: true,
+        }),
+        name => panic!("Unknown cost {name:?}"),
+    };
+    if let Some(cap) = cap {
+        fuel_cost = Box::new(Capped {
+            cost: fuel_cost,
+            cap,
+        });
+    }
+    if !weights.is_empty() {
+        fuel_cost = Box::new(Weighted {
+            cost: fuel_cost,
+            weights,
+        });
+    }
+
+    let alignment = optimal_alignment(INPUT, &fuel_cost);
+    println!("fuel: {} at {}", alignment.fuel, alignment.destination());
+
+    if show_ties {
+        for ties in alignment.ties() {
+            println!("tied: {}..={}", ties.start(), ties.end());
+        }
+    }
+    if show_per_crab {
+        for crab in alignment.per_crab() {
+            let fuel = crab.fuel_per_crab;
+            println!("{}: {} crabs x {fuel}", crab.position, crab.n_crabs);
+        }
+    }
+    if show_curve {
+        for (destination, fuel) in alignment.curve() {
+            println!("{destination},{fuel}");
+        }
+    }
+    if let Some(k) = rally {
+        let gathering = gather(INPUT, k, &fuel_cost);
+        println!("fuel with {k} rally points: {}", gathering.fuel);
+        for rally_point in &gathering.rally_points {
+            let n_crabs: u64 = rally_point.crabs.values().sum();
+            let fuel = rally_point.fuel;
+            println!("{}: {fuel} for {n_crabs} crabs", rally_point.destination);
+        }
+    }
 }
 
-fn minimum_alignment_fuel(s: &str, fuel_cost: impl Fn(&Positions, Coord) -> u64) -> u64 {
-    minimum_alignment_fuel_inner(s, fuel_cost).unwrap()
+fn minimum_alignment_fuel(s: &str, fuel_cost: &impl FuelCost) -> u64 {
+    minimum_alignment_fuel_inner(s, fuel_cost, fuel_cost.search()).unwrap()
+}
+
+/// How to find the best destination, depending on what is known about
+/// the shape of the fuel cost.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Search {
+    /// Fuel grows linearly with distance, so a median is optimal
+    Median,
+    /// Fuel is `d * (d + 1) / 2`, so the optimum is within 1 of the mean
+    NearMean,
+    /// Any fuel cost that is convex in the destination
+    Convex,
+    /// Try every destination
+    Exhaustive,
 }
 
 type Coord = u32;
 type Positions = BTreeMap<Coord, u64>;
 
-fn minimum_alignment_fuel_inner(
+/// A model of how much fuel a crab burns to move.
+trait FuelCost {
+    /// Fuel for a single crab at `crab` to reach `destination`.
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64;
+
+    /// What is known about the shape of the cost. Anything that is
+    /// convex in the distance and never decreases as the distance grows
+    /// should say [`Search::Convex`]; a cost that is only convex, such
+    /// as one that is cheapest a few steps away, must not.
+    fn search(&self) -> Search {
+        Search::Exhaustive
+    }
+
+    /// Whether the cost never decreases as the distance grows, which
+    /// [`gather`] relies on. A cost with a faster search than
+    /// [`Search::Exhaustive`] already promises this.
+    fn nondecreasing(&self) -> bool {
+        self.search() != Search::Exhaustive
+    }
+
+    fn total_fuel(&self, positions: &Positions, destination: Coord) -> u64 {
+        positions
+            .iter()
+            .map(|(&pos, &count)| self.crab_fuel(pos, destination) * count)
+            .sum()
+    }
+}
+
+impl<C: FuelCost + ?Sized> FuelCost for Box<C> {
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        (**self).crab_fuel(crab, destination)
+    }
+
+    fn search(&self) -> Search {
+        (**self).search()
+    }
+
+    fn nondecreasing(&self) -> bool {
+        (**self).nondecreasing()
+    }
+
+    fn total_fuel(&self, positions: &Positions, destination: Coord) -> u64 {
+        (**self).total_fuel(positions, destination)
+    }
+}
+
+fn distance(crab: Coord, destination: Coord) -> u64 {
+    u64::from(Coord::abs_diff(crab, destination))
+}
+
+/// One unit of fuel per step.
+#[derive(Debug, Copy, Clone)]
+struct Linear;
+
+impl FuelCost for Linear {
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        distance(crab, destination)
+    }
+
+    fn search(&self) -> Search {
+        Search::Median
+    }
+}
+
+/// Each step costs one more than the last.
+#[derive(Debug, Copy, Clone)]
+struct Ramped;
+
+impl FuelCost for Ramped {
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        inclusive_sum_down_to_zero(distance(crab, destination))
+    }
+
+    fn search(&self) -> Search {
+        Search::NearMean
+    }
+}
+
+/// The square of the distance.
+#[derive(Debug, Copy, Clone)]
+struct Quadratic;
+
+impl FuelCost for Quadratic {
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        distance(crab, destination).pow(2)
+    }
+
+    fn search(&self) -> Search {
+        Search::Convex
+    }
+}
+
+/// Any cost computed from the distance alone.
+#[derive(Debug, Copy, Clone)]
+struct PerDistance<F> {
+    cost: F,
+    /// Whether `cost` is convex and never decreases as the distance
+    /// grows, which allows [`Search::Convex`]
+    convex: bool,
+    /// Whether `cost` never decreases as the distance grows, even if it
+    /// is not convex
+    nondecreasing: bool,
+}
+
+impl<F> FuelCost for PerDistance<F>
+where
+    F: Fn(u64) -> u64,
+{
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        (self.cost)(distance(crab, destination))
+    }
+
+    fn search(&self) -> Search {
+        if self.convex {
+            Search::Convex
+        } else {
+            Search::Exhaustive
+        }
+    }
+
+    fn nondecreasing(&self) -> bool {
+        self.convex || self.nondecreasing
+    }
+}
+
+/// No crab ever burns more than `cap`. This flattens the cost for
+/// far-away crabs, so it is no longer convex.
+#[derive(Debug, Copy, Clone)]
+struct Capped<C> {
+    cost: C,
+    cap: u64,
+}
+
+impl<C: FuelCost> FuelCost for Capped<C> {
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        self.cost.crab_fuel(crab, destination).min(self.cap)
+    }
+
+    fn nondecreasing(&self) -> bool {
+        self.cost.nondecreasing()
+    }
+}
+
+/// Crabs at some positions are more expensive to move than
+/// others. Positions without a weight count once.
+#[derive(Debug, Clone)]
+struct Weighted<C> {
+    cost: C,
+    weights: BTreeMap<Coord, u64>,
+}
+
+impl<C: FuelCost> FuelCost for Weighted<C> {
+    fn crab_fuel(&self, crab: Coord, destination: Coord) -> u64 {
+        let weight = self.weights.get(&crab).copied().unwrap_or(1);
+        self.cost.crab_fuel(crab, destination) * weight
+    }
+
+    // A weighted sum of convex costs is still convex, but the median
+    // and mean shortcuts assume every crab counts the same.
+    fn search(&self) -> Search {
+        match self.cost.search() {
+            Search::Median | Search::NearMean | Search::Convex => Search::Convex,
+            Search::Exhaustive => Search::Exhaustive,
+        }
+    }
+
+    fn nondecreasing(&self) -> bool {
+        self.cost.nondecreasing()
+    }
+}
+
+fn minimum_alignment_fuel_inner(s: &str, fuel_cost: &impl FuelCost, search: Search) -> Result<u64> {
+    optimal_alignment_inner(s, fuel_cost, search).map(|a| a.fuel)
+}
+
+fn optimal_alignment<'a, C: FuelCost>(s: &str, fuel_cost: &'a C) -> Alignment<'a, C> {
+    optimal_alignment_inner(s, fuel_cost, fuel_cost.search()).unwrap()
+}
+
+fn optimal_alignment_inner<'a, C: FuelCost>(
     s: &str,
-    fuel_cost: impl Fn(&Positions, Coord) -> u64,
-) -> Result<u64> {
+    fuel_cost: &'a C,
+    search: Search,
+) -> Result<Alignment<'a, C>> {
+    let positions = parse_positions(s)?;
+    Ok(Alignment::new(positions, fuel_cost, search))
+}
+
+fn parse_positions(s: &str) -> Result<Positions> {
     let mut positions = Positions::new();
     for pos in s.split(',').map(|p| p.trim().parse()) {
         let pos = pos?;
         *positions.entry(pos).or_default() += 1;
     }
 
-    let &min = positions
-        .keys()
-        .next()
-        .ok_or("Need at least one position")?;
-    let &max = positions
-        .keys()
-        .next_back()
-        .ok_or("Need at least one position")?;
+    if positions.is_empty() {
+        return Err("Need at least one position".into());
+    }
 
-    (min..=max)
-        .map(|destination| fuel_cost(&positions, destination))
-        .min()
-        .ok_or("Need at least one position")
-        .map_err(Into::into)
+    Ok(positions)
 }
 
-fn fuel_cost_linear(positions: &Positions, destination: Coord) -> u64 {
-    sum_fuel_computation(positions, |pos| {
-        u64::from(Coord::abs_diff(pos, destination))
-    })
+/// The cheapest way to line every crab up at a single destination.
+#[derive(Debug, Clone)]
+struct Alignment<'a, C> {
+    fuel_cost: &'a C,
+    positions: Positions,
+    search: Search,
+    fuel: u64,
+    destination: Coord,
+}
+
+/// The fuel spent by each crab starting at `position`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct CrabFuel {
+    position: Coord,
+    n_crabs: u64,
+    fuel_per_crab: u64,
+}
+
+impl<'a, C: FuelCost> Alignment<'a, C> {
+    /// `positions` must not be empty.
+    fn new(positions: Positions, fuel_cost: &'a C, search: Search) -> Self {
+        let (min, max) = Self::bounds(&positions);
+        let fuel = |destination| fuel_cost.total_fuel(&positions, destination);
+
+        let destination = Self::fast_destination(&positions, search, fuel).unwrap_or_else(|| {
+            (min..=max)
+                .min_by_key(|&d| fuel(d))
+                .expect("Bounds are ordered")
+        });
+
+        Self {
+            fuel_cost,
+            fuel: fuel(destination),
+            positions,
+            search,
+            destination,
+        }
+    }
+
+    fn bounds(positions: &Positions) -> (Coord, Coord) {
+        let min = positions.keys().next();
+        let max = positions.keys().next_back();
+        let (&min, &max) = min.zip(max).expect("Positions must not be empty");
+        (min, max)
+    }
+
+    /// One optimal destination, when the cost is known to be convex.
+    fn fast_destination(
+        positions: &Positions,
+        search: Search,
+        fuel: impl Fn(Coord) -> u64,
+    ) -> Option<Coord> {
+        let (min, max) = Self::bounds(positions);
+
+        let destination = match search {
+            Search::Median => weighted_median(positions),
+            Search::NearMean => {
+                let mean = weighted_mean(positions);
+                let lo = mean.saturating_sub(1).max(min);
+                let hi = mean.saturating_add(1).min(max);
+                (lo..=hi)
+                    .min_by_key(|&d| fuel(d))
+                    .expect("Mean is within the positions")
+            }
+            Search::Convex => convex_minimum(min, max, fuel),
+            Search::Exhaustive => return None,
+        };
+
+        Some(destination)
+    }
+
+    /// An optimal destination. There may be others; see [`Self::ties`].
+    fn destination(&self) -> Coord {
+        self.destination
+    }
+
+    /// Every destination that needs exactly `fuel`, as ascending runs.
+    ///
+    /// With a convex cost the ties are a single run around
+    /// `destination()`, and its ends are found by binary search.
+    /// Otherwise every destination between the outermost crabs is tried.
+    fn ties(&self) -> Vec<RangeInclusive<Coord>> {
+        let (min, max) = Self::bounds(&self.positions);
+        let fuel = |destination| self.fuel_cost.total_fuel(&self.positions, destination);
+
+        if self.search != Search::Exhaustive {
+            // Fuel never increases up to `destination` and never
+            // decreases after it
+            let first = first_true(min, self.destination, |d| fuel(d) <= self.fuel);
+            let last = last_true(self.destination, max, |d| fuel(d) <= self.fuel);
+            return vec![first..=last];
+        }
+
+        let tied = (min..=max).filter(|&d| fuel(d) == self.fuel);
+        let mut runs: Vec<RangeInclusive<Coord>> = Vec::new();
+        for destination in tied {
+            match runs.last_mut() {
+                Some(run) if *run.end() + 1 == destination => *run = *run.start()..=destination,
+                _ => runs.push(destination..=destination),
+            }
+        }
+        runs
+    }
+
+    /// How much fuel each group of crabs spends to reach `destination()`.
+    fn per_crab(&self) -> impl Iterator<Item = CrabFuel> + '_ {
+        let destination = self.destination();
+        self.positions
+            .iter()
+            .map(move |(&position, &n_crabs)| CrabFuel {
+                position,
+                n_crabs,
+                fuel_per_crab: self.fuel_cost.crab_fuel(position, destination),
+            })
+    }
+
+    /// The total fuel for every destination between the outermost crabs.
+    fn curve(&self) -> impl Iterator<Item = (Coord, u64)> + '_ {
+        let (min, max) = Self::bounds(&self.positions);
+        (min..=max).map(move |d| (d, self.fuel_cost.total_fuel(&self.positions, d)))
+    }
 }
 
-fn fuel_cost_ramped(positions: &Positions, destination: Coord) -> u64 {
-    sum_fuel_computation(positions, |pos| {
-        let dist = u64::from(Coord::abs_diff(pos, destination));
-        inclusive_sum_down_to_zero(dist)
+fn gather<C: FuelCost>(s: &str, k: usize, fuel_cost: &C) -> Gathering {
+    gather_inner(s, k, fuel_cost).unwrap()
+}
+
+/// Splits the crabs across up to `k` destinations.
+///
+/// The cost must never decrease with distance, so each crab is best off
+/// at its nearest rally point and every rally point serves a contiguous
+/// run of the sorted positions. Dynamic programming over where those
+/// runs split finds the exact optimum.
+fn gather_inner<C: FuelCost>(s: &str, k: usize, fuel_cost: &C) -> Result<Gathering> {
+    if k == 0 {
+        return Err("Need at least one rally point".into());
+    }
+    if !fuel_cost.nondecreasing() {
+        return Err("Gathering needs a cost that never decreases with distance".into());
+    }
+
+    let positions = parse_positions(s)?;
+    let sorted: Vec<_> = positions.into_iter().collect();
+    let n = sorted.len();
+    // More rally points than distinct positions can't help
+    let k = k.min(n);
+
+    // groups[i][j - i - 1] is the least fuel for `sorted[i..j]` to meet
+    // at a single rally point, and the lowest such rally point
+    let groups: Vec<_> = (0..n)
+        .map(|i| match fuel_cost.search() {
+            Search::Exhaustive => scanned_runs(&sorted[i..], fuel_cost),
+            _ => convex_runs(&sorted[i..], fuel_cost),
+        })
+        .collect();
+    let group = |i: usize, j: usize| groups[i][j - i - 1];
+
+    // best[g][j] is the least fuel for `sorted[..j]` with `g` rally
+    // points; split[g][j] is where the last of those starts.
+    let mut best = vec![vec![u64::MAX; n + 1]; k + 1];
+    let mut split = vec![vec![0; n + 1]; k + 1];
+    best[0][0] = 0;
+
+    for g in 1..=k {
+        for j in g..=n {
+            for i in g - 1..j {
+                if best[g - 1][i] == u64::MAX {
+                    continue;
+                }
+                let fuel = best[g - 1][i] + group(i, j).0;
+                if fuel < best[g][j] {
+                    best[g][j] = fuel;
+                    split[g][j] = i;
+                }
+            }
+        }
+    }
+
+    let mut rally_points = Vec::with_capacity(k);
+    let mut j = n;
+    for g in (1..=k).rev() {
+        let i = split[g][j];
+        let (fuel, destination) = group(i, j);
+        rally_points.push(RallyPoint {
+            destination,
+            fuel,
+            crabs: sorted[i..j].iter().copied().collect(),
+        });
+        j = i;
+    }
+    rally_points.reverse();
+
+    Ok(Gathering {
+        fuel: best[k][n],
+        rally_points,
     })
 }
 
-fn sum_fuel_computation(positions: &Positions, f: impl Fn(Coord) -> u64) -> u64 {
-    positions.iter().map(|(&pos, &count)| f(pos) * count).sum()
+/// The best rally point for each run `crabs[..j]`, as `(fuel,
+/// destination)`. No crab gets closer by meeting outside the run, and
+/// for a convex cost the fuel has a single dip within it.
+fn convex_runs(crabs: &[(Coord, u64)], fuel_cost: &impl FuelCost) -> Vec<(u64, Coord)> {
+    let first = crabs[0].0;
+
+    (1..=crabs.len())
+        .map(|j| {
+            let run = &crabs[..j];
+            let fuel = |destination| {
+                run.iter()
+                    .map(|&(pos, count)| fuel_cost.crab_fuel(pos, destination) * count)
+                    .sum::<u64>()
+            };
+            let destination = convex_minimum(first, run[j - 1].0, fuel);
+            (fuel(destination), destination)
+        })
+        .collect()
+}
+
+/// Like [`convex_runs`], for any cost, by keeping the fuel to every
+/// destination within the run up to date as the run grows.
+fn scanned_runs(crabs: &[(Coord, u64)], fuel_cost: &impl FuelCost) -> Vec<(u64, Coord)> {
+    let first = crabs[0].0;
+    // totals[d - first] is the fuel for the run so far to reach `d`
+    let mut totals: Vec<u64> = Vec::new();
+
+    let mut runs = Vec::with_capacity(crabs.len());
+    for (j, &(position, count)) in crabs.iter().enumerate() {
+        // The run now reaches `position`, so the crabs already in it
+        // need the fuel to get that far too
+        let reached = totals.len();
+        totals.extend((first..=position).skip(reached).map(|destination| {
+            crabs[..j]
+                .iter()
+                .map(|&(pos, count)| fuel_cost.crab_fuel(pos, destination) * count)
+                .sum::<u64>()
+        }));
+        for (destination, total) in (first..=position).zip(&mut totals) {
+            *total += fuel_cost.crab_fuel(position, destination) * count;
+        }
+
+        let (offset, &fuel) = totals
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &fuel)| fuel)
+            .expect("Run is not empty");
+        runs.push((fuel, first + offset as Coord));
+    }
+
+    runs
+}
+
+/// Crabs lined up at several destinations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Gathering {
+    fuel: u64,
+    /// In ascending order of destination
+    rally_points: Vec<RallyPoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RallyPoint {
+    destination: Coord,
+    fuel: u64,
+    /// The crabs that move here
+    crabs: Positions,
+}
+
+fn weighted_median(positions: &Positions) -> Coord {
+    let n_crabs: u64 = positions.values().sum();
+
+    let mut seen = 0;
+    for (&pos, &count) in positions {
+        seen += count;
+        if seen * 2 >= n_crabs {
+            return pos;
+        }
+    }
+
+    unreachable!("Positions must not be empty")
+}
+
+/// Rounded down.
+fn weighted_mean(positions: &Positions) -> Coord {
+    let n_crabs: u128 = positions.values().map(|&c| u128::from(c)).sum();
+    let sum: u128 = positions
+        .iter()
+        .map(|(&pos, &count)| u128::from(pos) * u128::from(count))
+        .sum();
+
+    (sum / n_crabs) as Coord
+}
+
+/// The integer form of a ternary search: a convex function stops
+/// decreasing at its minimum, so binary search for that point.
+fn convex_minimum(mut lo: Coord, mut hi: Coord, f: impl Fn(Coord) -> u64) -> Coord {
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if f(mid) <= f(mid + 1) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    lo
+}
+
+/// The lowest value in `lo..=hi` where `pred` holds, given that it is
+/// false up to some point and true from then on, including at `hi`.
+fn first_true(mut lo: Coord, mut hi: Coord, pred: impl Fn(Coord) -> bool) -> Coord {
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    lo
+}
+
+/// The highest value in `lo..=hi` where `pred` holds, given that it is
+/// true up to some point, including at `lo`, and false from then on.
+fn last_true(mut lo: Coord, mut hi: Coord, pred: impl Fn(Coord) -> bool) -> Coord {
+    while lo < hi {
+        let mid = hi - (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    lo
 }
 
 fn inclusive_sum_down_to_zero(value: u64) -> u64 {
-    if value % 2 == 0 {
+    if value.is_multiple_of(2) {
         (value / 2) * (value + 1)
     } else {
         inclusive_sum_down_to_zero(value - 1) + value
@@ -73,17 +670,264 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 #[cfg(test)]
 mod test {
     use super::*;
+    use itertools::Itertools;
 
     const TEST_INPUT: &str = include_str!("../test-input");
 
     #[test]
     fn test_part1() {
-        assert_eq!(37, minimum_alignment_fuel(TEST_INPUT, fuel_cost_linear));
+        assert_eq!(37, minimum_alignment_fuel(TEST_INPUT, &Linear));
     }
 
     #[test]
     fn test_part2() {
-        assert_eq!(168, minimum_alignment_fuel(TEST_INPUT, fuel_cost_ramped));
+        assert_eq!(168, minimum_alignment_fuel(TEST_INPUT, &Ramped));
+    }
+
+    fn exhaustive(s: &str, fuel_cost: &impl FuelCost) -> u64 {
+        minimum_alignment_fuel_inner(s, fuel_cost, Search::Exhaustive).unwrap()
+    }
+
+    fn with_search(s: &str, fuel_cost: &impl FuelCost, search: Search) -> u64 {
+        minimum_alignment_fuel_inner(s, fuel_cost, search).unwrap()
+    }
+
+    /// Small inputs with duplicates, ties and lopsided spreads.
+    const CASES: &[&str] = &[
+        "0",
+        "5,5,5",
+        "3,7",
+        "0,1,2,10,11,12",
+        "1,1,1,1,199",
+        "16,1,2,0,4,2,7,1,2,14",
+        "150,3,77,77,42,199,8,120",
+        "9,180,181,60,61,62,130,2,2,45,97",
+    ];
+
+    #[test]
+    fn test_searches_match_exhaustive() {
+        for &input in CASES {
+            for search in [Search::Median, Search::Convex] {
+                assert_eq!(
+                    exhaustive(input, &Linear),
+                    with_search(input, &Linear, search),
+                    "{search:?} for {input}",
+                );
+            }
+            for search in [Search::NearMean, Search::Convex] {
+                assert_eq!(
+                    exhaustive(input, &Ramped),
+                    with_search(input, &Ramped, search),
+                    "{search:?} for {input}",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sparse_huge_positions() {
+        let input = "1,4294967290,4294967295";
+
+        assert_eq!(4294967294, minimum_alignment_fuel(input, &Linear));
+        assert_eq!(
+            minimum_alignment_fuel(input, &Ramped),
+            with_search(input, &Ramped, Search::Convex),
+        );
+    }
+
+    #[test]
+    fn test_user_costs() {
+        let cube = PerDistance {
+            cost: |d: u64| d.pow(3),
+            convex: true,
+            nondecreasing: true,
+        };
+        let capped = Capped {
+            cost: Quadratic,
+            cap: 50,
+        };
+        let weighted = Weighted {
+            cost: Linear,
+            weights: [(16, 10), (14, 5)].into_iter().collect(),
+        };
+
+        for &input in CASES.iter().chain([&TEST_INPUT]) {
+            assert_eq!(
+                exhaustive(input, &Quadratic),
+                minimum_alignment_fuel(input, &Quadratic)
+            );
+            assert_eq!(
+                exhaustive(input, &cube),
+                minimum_alignment_fuel(input, &cube)
+            );
+            assert_eq!(
+                exhaustive(input, &weighted),
+                minimum_alignment_fuel(input, &weighted)
+            );
+        }
+
+        assert_eq!(Search::Exhaustive, capped.search());
+        assert_eq!(Search::Convex, weighted.search());
+
+        // Gathering at 2, the crabs at 14 and 16 both pay the cap
+        assert_eq!(
+            50 + 1 + 1 + 4 + 4 + 25 + 50,
+            minimum_alignment_fuel(TEST_INPUT, &capped)
+        );
+    }
+
+    #[test]
+    fn test_alignment_details() {
+        let alignment = optimal_alignment(TEST_INPUT, &Linear);
+
+        assert_eq!(37, alignment.fuel);
+        assert_eq!(2, alignment.destination());
+        assert_eq!([2..=2], &*alignment.ties());
+
+        let per_crab: Vec<_> = alignment.per_crab().collect();
+        assert_eq!(
+            CrabFuel {
+                position: 1,
+                n_crabs: 2,
+                fuel_per_crab: 1
+            },
+            per_crab[1],
+        );
+        let total: u64 = per_crab.iter().map(|c| c.n_crabs * c.fuel_per_crab).sum();
+        assert_eq!(alignment.fuel, total);
+
+        let curve: Vec<_> = alignment.curve().collect();
+        assert_eq!(17, curve.len());
+        assert_eq!((0, 49), curve[0]);
+        assert_eq!((2, 37), curve[2]);
+        assert_eq!((10, 71), curve[10]);
+    }
+
+    #[test]
+    fn test_tied_destinations() {
+        // Anywhere between the two crabs is equally good
+        let alignment = optimal_alignment("3,7", &Linear);
+        assert_eq!(4, alignment.fuel);
+        assert_eq!([3..=7], &*alignment.ties());
+
+        let capped = Capped {
+            cost: Linear,
+            cap: 1,
+        };
+        let alignment = optimal_alignment("0,10", &capped);
+        assert_eq!(1, alignment.fuel);
+        assert_eq!([0..=0, 10..=10], &*alignment.ties());
+
+        for &input in CASES {
+            for search in [Search::Median, Search::Convex] {
+                let fast = optimal_alignment_inner(input, &Linear, search).unwrap();
+                let slow = optimal_alignment_inner(input, &Linear, Search::Exhaustive).unwrap();
+                assert_eq!(slow.ties(), fast.ties(), "{search:?} for {input}");
+            }
+        }
+
+        // Far too many ties to list one by one
+        let alignment = optimal_alignment("0,4294967295", &Linear);
+        assert_eq!(4294967295, alignment.fuel);
+        assert_eq!([0..=4294967295], &*alignment.ties());
+    }
+
+    #[test]
+    fn test_gather() {
+        let one = gather(TEST_INPUT, 1, &Linear);
+        assert_eq!(37, one.fuel);
+        assert_eq!(2, one.rally_points[0].destination);
+
+        let two = gather(TEST_INPUT, 2, &Linear);
+        assert_eq!(13, two.fuel);
+        let destinations: Vec<_> = two.rally_points.iter().map(|r| r.destination).collect();
+        assert_eq!([2, 14], &*destinations);
+        assert_eq!(
+            [(14, 1), (16, 1)].into_iter().collect::<Positions>(),
+            two.rally_points[1].crabs
+        );
+
+        // A rally point at every crab
+        let many = gather(TEST_INPUT, 100, &Ramped);
+        assert_eq!(0, many.fuel);
+        assert_eq!(7, many.rally_points.len());
+
+        assert!(gather_inner(TEST_INPUT, 0, &Linear).is_err());
+
+        // Only the crabs' own span is ever searched
+        let sparse = gather("0,4294967295", 2, &Linear);
+        assert_eq!(0, sparse.fuel);
+        let sparse = gather("0,4294967295", 1, &Ramped);
+        assert_eq!(1 << 62, sparse.fuel);
+
+        // Cheapest 3 steps away, so crabs may pass a nearer rally point
+        let offset = PerDistance {
+            cost: |d: u64| (d as i64 - 3).pow(2) as u64,
+            convex: false,
+            nondecreasing: false,
+        };
+        assert!(gather_inner("0,1,2,10,11,12", 2, &offset).is_err());
+    }
+
+    #[test]
+    fn test_gather_matches_brute_force() {
+        fn brute_force(positions: &Positions, k: usize, fuel_cost: &impl FuelCost) -> u64 {
+            let (&min, &max) = positions
+                .keys()
+                .next()
+                .zip(positions.keys().next_back())
+                .unwrap();
+            (min..=max)
+                .combinations(k)
+                .map(|destinations| {
+                    positions
+                        .iter()
+                        .map(|(&pos, &count)| {
+                            let fuel = destinations.iter().map(|&d| fuel_cost.crab_fuel(pos, d));
+                            fuel.min().unwrap() * count
+                        })
+                        .sum()
+                })
+                .min()
+                .unwrap()
+        }
+
+        let capped = Capped {
+            cost: Quadratic,
+            cap: 30,
+        };
+        // Not convex, but never decreasing
+        let stepped = PerDistance {
+            cost: |d: u64| d.div_ceil(3),
+            convex: false,
+            nondecreasing: true,
+        };
+        for input in CASES {
+            let input: String = input
+                .split(',')
+                .take(6)
+                .map(|p| (p.parse::<u32>().unwrap() / 10).to_string())
+                .join(",");
+            let positions = parse_positions(&input).unwrap();
+
+            for k in 1..=3.min(positions.len()) {
+                assert_eq!(
+                    brute_force(&positions, k, &Ramped),
+                    gather(&input, k, &Ramped).fuel,
+                    "{k} for {input}"
+                );
+                assert_eq!(
+                    brute_force(&positions, k, &capped),
+                    gather(&input, k, &capped).fuel,
+                    "{k} for {input}"
+                );
+                assert_eq!(
+                    brute_force(&positions, k, &stepped),
+                    gather(&input, k, &stepped).fuel,
+                    "{k} for {input}"
+                );
+            }
+        }
     }
 
     #[test]
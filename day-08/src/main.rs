@@ -1,7 +1,10 @@
+use itertools::Itertools;
+use snafu::{ensure, OptionExt, Snafu};
 use std::{
     collections::BTreeMap,
+    env,
     fmt::Display,
-    ops::{Add, Sub},
+    ops::{Add, BitAnd, BitOr, BitXor, ControlFlow, Sub},
     str::FromStr,
 };
 
@@ -10,6 +13,53 @@ const INPUT: &str = include_str!("../input");
 fn main() {
     println!("part1: {}", output_digits_with_unique_segments(INPUT));
     println!("part2: {}", output_value_sum(INPUT));
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            // Part 2 again, through the decoder for any alphabet
+            "--generic" => {
+                let alphabet = Alphabet::seven_segment_digits();
+                let sum: u64 = INPUT
+                    .lines()
+                    .map(|l| alphabet.output_value(l).unwrap())
+                    .sum();
+                println!("generic: {sum}");
+            }
+            // Decodes one `patterns | output` line of a hex display
+            "--hex" => {
+                let line = args.next().expect("--hex needs a line");
+                let value = Alphabet::seven_segment_hex().output_value(&line).unwrap();
+                println!("hex: {value:X}");
+            }
+            // Decodes part 2 as if each segment may have flipped with
+            // this probability, e.g. `--noisy 0.01`
+            "--noisy" => {
+                let value = args.next().and_then(|p| p.parse().ok());
+                let flip_probability = value.expect("--noisy needs a probability");
+
+                let (mut sum, mut confidence) = (0, 1.0_f64);
+                for line in INPUT.lines() {
+                    let entry: Entry = line.parse().unwrap();
+                    let decoded = entry.decode_noisy(flip_probability).unwrap();
+                    sum += decoded.readings[0].value;
+                    confidence = confidence.min(decoded.confidence);
+                }
+                println!("noisy: {sum} (lowest confidence {confidence:.3})");
+            }
+            // Draws what each output should have shown, then the
+            // output as it was wired with the wrong segments marked
+            "--show" => {
+                for line in INPUT.lines() {
+                    let entry: Entry = line.parse().unwrap();
+                    let intended = entry.intended_output().unwrap();
+                    println!("{}", intended.render());
+                    println!("{}", entry.1.render_against(&intended));
+                }
+            }
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
 }
 
 fn output_digits_with_unique_segments(s: &str) -> usize {
@@ -28,8 +78,9 @@ fn output_value_sum(s: &str) -> usize {
 }
 
 fn output_value_sum_inner(s: &str) -> Result<usize> {
-    let i = s.lines().map(|l| l.trim().parse::<Entry>());
-    itertools::process_results(i, |i| i.map(|e| e.output_value()).sum())
+    s.lines()
+        .map(|l| l.trim().parse::<Entry>()?.output_value())
+        .sum()
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -40,17 +91,52 @@ impl Entry {
         self.1.digits_with_unique_segments()
     }
 
-    fn output_value(&self) -> usize {
-        let analyzed = self.0.careful_analysis();
+    fn output_value(&self) -> Result<usize> {
+        let glyphs = self.output_glyphs()?;
+        Ok(glyphs.into_iter().fold(0, |sum, v| sum * 10 + v))
+    }
 
-        self.1 .0.iter().fold(0, |sum, d| {
-            let v = analyzed
-                .iter()
-                .enumerate()
-                .find_map(|(i, a)| (a == d).then(|| i))
-                .expect("No match found");
-            sum * 10 + v
-        })
+    fn output_glyphs(&self) -> Result<Vec<usize>> {
+        let analyzed = self.0.careful_analysis()?;
+
+        let Digits(output) = self.1;
+        let values: Vec<_> = output
+            .iter()
+            .map(|d| analyzed.iter().position(|a| a == d))
+            .collect();
+
+        let unmappable: Vec<_> = values.iter().positions(Option::is_none).collect();
+        ensure!(
+            unmappable.is_empty(),
+            UnmappableOutputSnafu {
+                outputs: unmappable
+            }
+        );
+
+        Ok(values.into_iter().flatten().collect())
+    }
+
+    /// What the output should have shown, had it been wired correctly.
+    fn intended_output(&self) -> Result<Output> {
+        let mut intended = [Digit::default(); 4];
+        for (d, glyph) in intended.iter_mut().zip(self.output_glyphs()?) {
+            *d = Digit::DIGITS[glyph];
+        }
+        Ok(Digits(intended))
+    }
+
+    /// Decodes even when patterns are missing or segments flipped.
+    /// Missing patterns are left empty by parsing, so they are skipped.
+    fn decode_noisy(&self, flip_probability: f64) -> Result<NoisyDecoding> {
+        let Self(Digits(input), Digits(output)) = self;
+        let patterns: Vec<_> = input
+            .iter()
+            .filter(|d| d.n_segments() > 0)
+            .map(|&d| d.into())
+            .collect();
+        let output: Vec<_> = output.iter().map(|&d| d.into()).collect();
+
+        Alphabet::seven_segment_digits().decode_noisy(&patterns, &output, flip_probability)
     }
 }
 
@@ -58,10 +144,7 @@ impl FromStr for Entry {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (i, o) = s
-            .trim()
-            .split_once('|')
-            .ok_or("Did not have exactly input and output")?;
+        let (i, o) = s.trim().split_once('|').context(MissingSeparatorSnafu)?;
         Ok(Self(i.trim().parse()?, o.trim().parse()?))
     }
 }
@@ -76,43 +159,116 @@ impl<const N: usize> Digits<N> {
     fn digits_with_unique_segments(&self) -> usize {
         self.0.iter().filter(|d| d.has_unique_segments()).count()
     }
+
+    /// Draws the digits side by side.
+    fn render(&self) -> Panel<'_, N> {
+        Panel {
+            shown: self,
+            intended: None,
+        }
+    }
+
+    /// Draws the digits side by side, marking every segment that
+    /// differs from `intended`.
+    fn render_against<'a>(&'a self, intended: &'a Self) -> Panel<'a, N> {
+        Panel {
+            shown: self,
+            intended: Some(intended),
+        }
+    }
+}
+
+/// Several digits next to each other, like a real display. When
+/// compared against what was intended, segments that are lit but
+/// should not be are upper case, and segments that should be lit but
+/// are not are `!`.
+#[derive(Debug, Copy, Clone)]
+struct Panel<'a, const N: usize> {
+    shown: &'a Digits<N>,
+    intended: Option<&'a Digits<N>>,
+}
+
+impl<const N: usize> Display for Panel<'_, N> {
+    fn fmt(&self, ft: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in Digit::ROWS {
+            let cells = self.shown.0.iter().enumerate().map(|(i, shown)| {
+                let intended = self.intended.map(|d| d.0[i]);
+                Digit::render_row(row, |segment| {
+                    let name = Digit::segment_name(segment);
+                    match (shown.segment(segment), intended.map(|d| d.segment(segment))) {
+                        (true, Some(false)) => name.to_ascii_uppercase(),
+                        (false, Some(true)) => '!',
+                        (true, _) => name,
+                        (false, _) => '.',
+                    }
+                })
+            });
+            writeln!(ft, "{}", cells.format("  "))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Digits<10> {
-    fn careful_analysis(&self) -> [Digit; 10] {
+    fn careful_analysis(&self) -> Result<[Digit; 10]> {
         let mut group = BTreeMap::<_, Vec<_>>::new();
         for &d in &self.0 {
             group.entry(d.n_segments()).or_default().push(d);
         }
-        let fives = &group[&5];
+        let group = |n_segments| group.get(&n_segments).map_or(&[][..], Vec::as_slice);
+        let missing = |glyph| MissingGlyphsSnafu {
+            glyphs: vec![glyph],
+        };
 
-        let s_1 = *group[&2].first().expect("Did not find 1");
-        let s_4 = *group[&4].first().expect("Did not find 4");
-        let s_7 = *group[&3].first().expect("Did not find 7");
-        let s_8 = *group[&7].first().expect("Did not find 8");
+        let unique = [(2, 1), (4, 4), (3, 7), (7, 8)];
+        let missing_unique: Vec<_> = unique
+            .iter()
+            .filter(|&&(n_segments, _)| group(n_segments).is_empty())
+            .map(|&(_, glyph)| glyph)
+            .collect();
+        ensure!(
+            missing_unique.is_empty(),
+            MissingGlyphsSnafu {
+                glyphs: missing_unique
+            }
+        );
+
+        let fives = group(5);
+
+        let s_1 = group(2)[0];
+        let s_4 = group(4)[0];
+        let s_7 = group(3)[0];
+        let s_8 = group(7)[0];
         let s_3 = *fives
             .iter()
             .find(|c| c.contains(&s_7))
-            .expect("Did not find 3");
+            .context(missing(3))?;
         let s_9 = s_3 + s_4;
         let s_e = s_8 - s_9;
         let s_b = s_8 - s_3 - s_e;
         let s_2 = *fives
             .iter()
             .find(|c| c.contains(&s_e))
-            .expect("Did not find 2");
+            .context(missing(2))?;
         let s_5 = *fives
             .iter()
             .find(|c| c.contains(&s_b))
-            .expect("Did not find 5");
+            .context(missing(5))?;
         let s_6 = s_5 + s_e;
         let s_0 = *self
             .0
             .iter()
             .find(|d| ![s_1, s_2, s_3, s_4, s_5, s_6, s_7, s_8, s_9].contains(d))
-            .expect("Dif not find 0");
+            .context(missing(0))?;
 
-        [s_0, s_1, s_2, s_3, s_4, s_5, s_6, s_7, s_8, s_9]
+        let analyzed = [s_0, s_1, s_2, s_3, s_4, s_5, s_6, s_7, s_8, s_9];
+
+        // 6 and 9 were built rather than found, so make sure they exist
+        let not_seen: Vec<_> = analyzed.iter().positions(|d| !self.0.contains(d)).collect();
+        ensure!(not_seen.is_empty(), MissingGlyphsSnafu { glyphs: not_seen });
+
+        Ok(analyzed)
     }
 }
 
@@ -122,7 +278,7 @@ impl<const N: usize> FromStr for Digits<N> {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut this = Self([Digit::default(); N]);
         for (i, word) in s.split_ascii_whitespace().enumerate() {
-            let i = this.0.get_mut(i).ok_or("Too many digits")?;
+            let i = this.0.get_mut(i).context(TooManyDigitsSnafu)?;
             *i = word.trim().parse()?;
         }
         Ok(this)
@@ -131,23 +287,24 @@ impl<const N: usize> FromStr for Digits<N> {
 
 macro_rules! digit_literal {
     ($a:ident, $b:ident, $c:ident, $d:ident, $e:ident, $f:ident, $g:ident) => {
-        Digit([
-            digit_literal!(@ $a),
-            digit_literal!(@ $b),
-            digit_literal!(@ $c),
-            digit_literal!(@ $d),
-            digit_literal!(@ $e),
-            digit_literal!(@ $f),
-            digit_literal!(@ $g),
-        ])
+        Digit(
+            digit_literal!(@ $a)
+                | digit_literal!(@ $b) << 1
+                | digit_literal!(@ $c) << 2
+                | digit_literal!(@ $d) << 3
+                | digit_literal!(@ $e) << 4
+                | digit_literal!(@ $f) << 5
+                | digit_literal!(@ $g) << 6,
+        )
     };
 
-    (@ t) => { true };
-    (@ f) => { false };
+    (@ t) => { 1 };
+    (@ f) => { 0 };
 }
 
+/// Segment `A` is the lowest bit.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
-struct Digit([bool; 7]);
+struct Digit(u8);
 
 impl Digit {
     //                              A  B  C  D  E  F  G
@@ -162,7 +319,6 @@ impl Digit {
     const D8: Self = digit_literal!(t, t, t, t, t, t, t);
     const D9: Self = digit_literal!(t, t, t, t, f, t, t);
 
-    #[allow(unused)]
     const DIGITS: [Self; 10] = [
         Self::D0,
         Self::D1,
@@ -176,6 +332,9 @@ impl Digit {
         Self::D9,
     ];
 
+    #[cfg(test)]
+    const N_SEGMENTS: usize = 7;
+
     fn has_unique_segments(&self) -> bool {
         const SEGMENTS_FOR_1: usize = 2;
         const SEGMENTS_FOR_4: usize = 4;
@@ -189,15 +348,40 @@ impl Digit {
     }
 
     fn n_segments(&self) -> usize {
-        self.0.iter().filter(|&&x| x).count()
+        self.0.count_ones() as usize
     }
 
     fn contains(&self, other: &Digit) -> bool {
-        other.active_segments().all(|i| self.0[i])
+        other.is_subset(self)
     }
 
+    fn is_subset(&self, other: &Digit) -> bool {
+        self.0 & !other.0 == 0
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    fn symmetric_difference(self, other: Self) -> Self {
+        Self(self.0 ^ other.0)
+    }
+
+    fn segment(&self, i: usize) -> bool {
+        self.0 & (1 << i) != 0
+    }
+
+    #[cfg(test)]
     fn active_segments(&self) -> impl Iterator<Item = usize> + '_ {
-        self.0.iter().enumerate().filter_map(|(i, s)| s.then(|| i))
+        (0..Self::N_SEGMENTS).filter(move |&i| self.segment(i))
     }
 }
 
@@ -205,15 +389,7 @@ impl Add for Digit {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        let mut this = self;
-
-        for (t, &r) in this.0.iter_mut().zip(rhs.0.iter()) {
-            if r {
-                *t = true;
-            }
-        }
-
-        this
+        self.union(rhs)
     }
 }
 
@@ -221,15 +397,31 @@ impl Sub for Digit {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        let mut this = self;
+        self.difference(rhs)
+    }
+}
 
-        for (t, &r) in this.0.iter_mut().zip(rhs.0.iter()) {
-            if r {
-                *t = false;
-            }
-        }
+impl BitOr for Digit {
+    type Output = Self;
 
-        this
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl BitAnd for Digit {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.intersection(rhs)
+    }
+}
+
+impl BitXor for Digit {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        self.symmetric_difference(rhs)
     }
 }
 
@@ -240,48 +432,462 @@ impl FromStr for Digit {
         let mut this = Self::default();
         for c in s.chars() {
             match c {
-                'a' => this.0[0] = true,
-                'b' => this.0[1] = true,
-                'c' => this.0[2] = true,
-                'd' => this.0[3] = true,
-                'e' => this.0[4] = true,
-                'f' => this.0[5] = true,
-                'g' => this.0[6] = true,
-                other => return Err(format!("Unknown character {:?}", other).into()),
+                'a'..='g' => this.0 |= 1 << (c as u8 - b'a'),
+                c => return UnknownSegmentSnafu { c }.fail(),
             }
         }
         Ok(this)
     }
 }
 
+impl Digit {
+    /// How each segment is laid out, by name.
+    const ROWS: [&'static str; 7] = [
+        " aaaa ", "b    c", "b    c", " dddd ", "e    f", "e    f", " gggg ",
+    ];
+
+    fn segment_name(segment: usize) -> char {
+        char::from(b'a' + segment as u8)
+    }
+
+    fn render_row(row: &str, mut paint: impl FnMut(usize) -> char) -> String {
+        row.chars()
+            .map(|c| match c {
+                'a'..='g' => paint(usize::from(c as u8 - b'a')),
+                c => c,
+            })
+            .collect()
+    }
+}
+
 impl Display for Digit {
     fn fmt(&self, ft: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let a = if self.0[0] { 'a' } else { '.' };
-        let b = if self.0[1] { 'b' } else { '.' };
-        let c = if self.0[2] { 'c' } else { '.' };
-        let d = if self.0[3] { 'd' } else { '.' };
-        let e = if self.0[4] { 'e' } else { '.' };
-        let f = if self.0[5] { 'f' } else { '.' };
-        let g = if self.0[6] { 'g' } else { '.' };
-
-        writeln!(ft, " {a}{a}{a}{a} ")?;
-        writeln!(ft, "{b}    {c}")?;
-        writeln!(ft, "{b}    {c}")?;
-        writeln!(ft, " {d}{d}{d}{d} ")?;
-        writeln!(ft, "{e}    {f}")?;
-        writeln!(ft, "{e}    {f}")?;
-        writeln!(ft, " {g}{g}{g}{g} ")?;
+        for row in Self::ROWS {
+            let row = Self::render_row(row, |segment| {
+                if self.segment(segment) {
+                    Self::segment_name(segment)
+                } else {
+                    '.'
+                }
+            });
+            writeln!(ft, "{row}")?;
+        }
 
         Ok(())
     }
 }
 
-type Error = Box<dyn std::error::Error>;
+/// A set of segments, one bit per segment.
+type Segments = u32;
+
+fn segment_bits(segments: Segments) -> impl Iterator<Item = u32> {
+    (0..Segments::BITS).filter(move |&i| segments & (1 << i) != 0)
+}
+
+/// Parses `"acf"`-style patterns, where `a` is segment 0.
+fn parse_segments(s: &str, n_segments: u32) -> Result<Segments> {
+    let mut segments = 0;
+    for c in s.chars() {
+        let i = u32::from(c).wrapping_sub(u32::from('a'));
+        ensure!(i < n_segments, UnknownSegmentSnafu { c });
+        segments |= 1 << i;
+    }
+    Ok(segments)
+}
+
+impl From<Digit> for Segments {
+    fn from(d: Digit) -> Self {
+        d.0.into()
+    }
+}
+
+/// The glyphs a display can show, each as the set of segments it
+/// lights. A glyph's value is its index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Alphabet {
+    n_segments: u32,
+    glyphs: Vec<Segments>,
+}
+
+/// `Wiring(w)[wire]` is the segment that `wire` actually drives.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Wiring(Vec<u32>);
+
+impl Wiring {
+    fn apply(&self, pattern: Segments) -> Segments {
+        segment_bits(pattern).fold(0, |acc, wire| acc | 1 << self.0[wire as usize])
+    }
+}
+
+impl Alphabet {
+    fn new(n_segments: u32, glyphs: &[&str]) -> Result<Self> {
+        ensure!(n_segments > 0, NoSegmentsSnafu);
+        ensure!(
+            n_segments <= Segments::BITS,
+            TooManySegmentsSnafu { n_segments }
+        );
+
+        let glyphs = glyphs
+            .iter()
+            .map(|g| parse_segments(g, n_segments))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { n_segments, glyphs })
+    }
+
+    fn seven_segment_digits() -> Self {
+        Self {
+            n_segments: 7,
+            glyphs: Digit::DIGITS.iter().map(|&d| d.into()).collect(),
+        }
+    }
+
+    /// `0`-`9` followed by `A`, `b`, `C`, `d`, `E` and `F`.
+    fn seven_segment_hex() -> Self {
+        let mut this = Self::seven_segment_digits();
+        let letters = Self::new(7, &["abcdef", "bdefg", "abeg", "cdefg", "abdeg", "abde"])
+            .expect("Hex letters are valid");
+        this.glyphs.extend(letters.glyphs);
+        this
+    }
+
+    fn all_segments(&self) -> Segments {
+        Segments::MAX >> (Segments::BITS - self.n_segments)
+    }
+
+    fn glyphs_like(&self, pattern: Segments) -> impl Iterator<Item = Segments> + '_ {
+        self.glyphs
+            .iter()
+            .copied()
+            .filter(move |g| g.count_ones() == pattern.count_ones())
+    }
+
+    fn glyph_value(&self, segments: Segments) -> Option<usize> {
+        self.glyphs.iter().position(|&g| g == segments)
+    }
+
+    /// Calls `visit` with each wiring that turns the scrambled
+    /// `patterns` into exactly the glyphs of this alphabet, until it
+    /// breaks. There can be a huge number of them.
+    fn solve(&self, patterns: &[Segments], mut visit: impl FnMut(Wiring) -> ControlFlow<()>) {
+        let all = self.all_segments();
+
+        // Each pattern must turn into a glyph with as many segments, so
+        // a wire that is lit (or dark) in the pattern can only drive a
+        // segment that is lit (or dark) in one of those glyphs.
+        let mut domains = vec![all; self.n_segments as usize];
+        for &pattern in patterns {
+            let (lit, dark) = self
+                .glyphs_like(pattern)
+                .fold((0, 0), |(lit, dark), g| (lit | g, dark | (all & !g)));
+
+            for (wire, domain) in domains.iter_mut().enumerate() {
+                *domain &= if pattern & (1 << wire) != 0 {
+                    lit
+                } else {
+                    dark
+                };
+            }
+        }
+
+        let mut assignment = vec![None; domains.len()];
+        let _ = self.search(patterns, &domains, &mut assignment, 0, &mut visit);
+    }
+
+    fn search(
+        &self,
+        patterns: &[Segments],
+        domains: &[Segments],
+        assignment: &mut Vec<Option<u32>>,
+        used: Segments,
+        visit: &mut impl FnMut(Wiring) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let next = (0..domains.len())
+            .filter(|&w| assignment[w].is_none())
+            .min_by_key(|&w| (domains[w] & !used).count_ones());
+
+        let wire = match next {
+            Some(wire) => wire,
+            None => {
+                let wiring = Wiring(assignment.iter().flatten().copied().collect());
+                if self.fits(&wiring, patterns) {
+                    visit(wiring)?;
+                }
+                return ControlFlow::Continue(());
+            }
+        };
+
+        for segment in segment_bits(domains[wire] & !used) {
+            assignment[wire] = Some(segment);
+            if self.consistent(patterns, assignment) {
+                self.search(patterns, domains, assignment, used | 1 << segment, visit)?;
+            }
+        }
+        assignment[wire] = None;
+        ControlFlow::Continue(())
+    }
+
+    /// Whether every pattern could still become a glyph once the rest
+    /// of the wires are assigned.
+    fn consistent(&self, patterns: &[Segments], assignment: &[Option<u32>]) -> bool {
+        patterns.iter().all(|&pattern| {
+            let (mut lit, mut dark) = (0, 0);
+            for (wire, segment) in assignment.iter().enumerate() {
+                if let Some(segment) = segment {
+                    if pattern & (1 << wire) != 0 {
+                        lit |= 1 << segment;
+                    } else {
+                        dark |= 1 << segment;
+                    }
+                }
+            }
+
+            self.glyphs_like(pattern)
+                .any(|g| g & lit == lit && g & dark == 0)
+        })
+    }
+
+    fn fits(&self, wiring: &Wiring, patterns: &[Segments]) -> bool {
+        let mut decoded: Vec<_> = patterns.iter().map(|&p| wiring.apply(p)).collect();
+        let mut glyphs = self.glyphs.clone();
+        decoded.sort_unstable();
+        glyphs.sort_unstable();
+        decoded == glyphs
+    }
+
+    /// Glyphs that some pattern may be missing for: there are fewer
+    /// patterns with as many segments than there are such glyphs.
+    fn missing_glyphs(&self, patterns: &[Segments]) -> Vec<usize> {
+        let n_patterns_like = |g: Segments| {
+            patterns
+                .iter()
+                .filter(|p| p.count_ones() == g.count_ones())
+                .count()
+        };
+
+        self.glyphs
+            .iter()
+            .positions(|&g| n_patterns_like(g) < self.glyphs_like(g).count())
+            .collect()
+    }
+
+    /// The value of each output pattern, as long as every possible
+    /// wiring agrees on it.
+    fn decode(&self, patterns: &[Segments], output: &[Segments]) -> Result<Vec<usize>> {
+        let mut first = None;
+        let mut disagreeing = None;
+
+        self.solve(patterns, |wiring| {
+            let decoded: Vec<_> = output
+                .iter()
+                .map(|&o| self.glyph_value(wiring.apply(o)))
+                .collect();
+
+            match &first {
+                None => first = Some((wiring, decoded)),
+                Some((_, expected)) if *expected != decoded => {
+                    disagreeing = Some(wiring);
+                    return ControlFlow::Break(());
+                }
+                Some(_) => {}
+            }
+            ControlFlow::Continue(())
+        });
+
+        let (wiring, first) = match first {
+            Some(first) => first,
+            None => {
+                let missing = self.missing_glyphs(patterns);
+                ensure!(missing.is_empty(), MissingGlyphsSnafu { glyphs: missing });
+                return NoWiringSnafu.fail();
+            }
+        };
+
+        if let Some(other) = disagreeing {
+            return AmbiguousWiringSnafu {
+                wirings: vec![wiring, other],
+            }
+            .fail();
+        }
+
+        let unmappable: Vec<_> = first.iter().positions(Option::is_none).collect();
+        ensure!(
+            unmappable.is_empty(),
+            UnmappableOutputSnafu {
+                outputs: unmappable
+            }
+        );
+
+        Ok(first.iter().flatten().copied().collect())
+    }
+
+    /// Like `decode`, but tolerates missing patterns and segments that
+    /// flipped, each with `flip_probability`. Every wiring is scored
+    /// by how many segments it needs flipped, so this only suits small
+    /// displays.
+    ///
+    /// `flip_probability` must be above 0 and below 0.5; otherwise more
+    /// flips would be at least as likely as fewer.
+    fn decode_noisy(
+        &self,
+        patterns: &[Segments],
+        output: &[Segments],
+        flip_probability: f64,
+    ) -> Result<NoisyDecoding> {
+        ensure!(
+            flip_probability > 0.0 && flip_probability < 0.5,
+            InvalidFlipProbabilitySnafu { flip_probability }
+        );
+        ensure!(
+            self.n_segments <= MAX_NOISY_SEGMENTS,
+            TooManySegmentsSnafu {
+                n_segments: self.n_segments
+            }
+        );
+
+        let distance = |p: Segments, g: Segments| (p ^ g).count_ones();
+        let nearest_distance = |p| self.glyphs.iter().map(|&g| distance(p, g)).min();
+        let base = self.glyphs.len() as u64;
+
+        let scored: Vec<_> = (0..self.n_segments)
+            .permutations(self.n_segments as usize)
+            .map(Wiring)
+            .map(|wiring| {
+                let flips = patterns
+                    .iter()
+                    .chain(output)
+                    .flat_map(|&p| nearest_distance(wiring.apply(p)))
+                    .sum::<u32>();
+                (wiring, flips)
+            })
+            .collect();
+
+        let min_flips = scored
+            .iter()
+            .map(|&(_, f)| f)
+            .min()
+            .context(NoWiringSnafu)?;
+        let odds = flip_probability / (1.0 - flip_probability);
+        let weight = |flips: u32| odds.powi((flips - min_flips) as i32);
+        let total: f64 = scored.iter().map(|&(_, f)| weight(f)).sum();
+
+        let mut readings = BTreeMap::<u64, f64>::new();
+        for (wiring, flips) in &scored {
+            // Every closest glyph is an equally likely reading
+            let choices: Vec<Vec<_>> = output
+                .iter()
+                .map(|&o| {
+                    let o = wiring.apply(o);
+                    let best = nearest_distance(o).unwrap_or(0);
+                    self.glyphs
+                        .iter()
+                        .positions(|&g| distance(o, g) == best)
+                        .collect()
+                })
+                .collect();
+            let n_choices: usize = choices.iter().map(Vec::len).product();
+
+            for glyphs in choices.into_iter().multi_cartesian_product() {
+                let value = glyphs.iter().fold(0, |sum, &v| sum * base + v as u64);
+                *readings.entry(value).or_default() += weight(*flips) / total / n_choices as f64;
+            }
+        }
+
+        let mut readings: Vec<_> = readings
+            .into_iter()
+            .map(|(value, probability)| Reading { value, probability })
+            .collect();
+        readings.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+
+        let (wiring, flips) = scored
+            .into_iter()
+            .min_by_key(|&(_, f)| f)
+            .expect("There is a wiring");
+        let confidence = weight(flips) / total;
+
+        Ok(NoisyDecoding {
+            wiring,
+            flips,
+            confidence,
+            readings,
+        })
+    }
+
+    /// Decodes a `patterns | output` line into a number, using one
+    /// digit per glyph.
+    fn output_value(&self, s: &str) -> Result<u64> {
+        let (i, o) = s.trim().split_once('|').context(MissingSeparatorSnafu)?;
+        let parse = |s: &str| {
+            s.split_ascii_whitespace()
+                .map(|w| parse_segments(w, self.n_segments))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        let base = self.glyphs.len() as u64;
+        let values = self.decode(&parse(i)?, &parse(o)?)?;
+        Ok(values.iter().fold(0, |sum, &v| sum * base + v as u64))
+    }
+}
+
+/// Trying every wiring of a bigger display takes too long.
+const MAX_NOISY_SEGMENTS: u32 = 8;
+
+/// The best guess at a display that dropped or flipped some segments.
+#[derive(Debug, Clone, PartialEq)]
+struct NoisyDecoding {
+    wiring: Wiring,
+    /// Segments that must have flipped if `wiring` is right
+    flips: u32,
+    /// How likely `wiring` is compared to every other wiring
+    confidence: f64,
+    /// Every way the output could read, most likely first
+    readings: Vec<Reading>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Reading {
+    value: u64,
+    probability: f64,
+}
+
+#[derive(Debug, Snafu)]
+enum Error {
+    MissingSeparator,
+    TooManyDigits,
+    UnknownSegment {
+        c: char,
+    },
+    NoSegments,
+    TooManySegments {
+        n_segments: u32,
+    },
+    /// Must be above 0 and below 0.5
+    InvalidFlipProbability {
+        flip_probability: f64,
+    },
+    /// These glyphs could not be found among the patterns
+    MissingGlyphs {
+        glyphs: Vec<usize>,
+    },
+    /// The patterns contradict each other
+    NoWiring,
+    /// More than one wiring fits, and they disagree about the output.
+    /// Only the first two that disagree are listed.
+    AmbiguousWiring {
+        wirings: Vec<Wiring>,
+    },
+    /// These outputs are not glyphs of the alphabet
+    UnmappableOutput {
+        outputs: Vec<usize>,
+    },
+}
+
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use itertools::Itertools;
 
     const TEST_INPUT: &str = include_str!("../test-input");
 
@@ -304,7 +910,292 @@ mod test {
 
     #[test]
     fn test_careful_analysis() {
-        let analyzed = Digits(Digit::DIGITS).careful_analysis();
+        let analyzed = Digits(Digit::DIGITS).careful_analysis().unwrap();
         assert_eq!(analyzed, Digit::DIGITS);
     }
+
+    #[test]
+    fn test_careful_analysis_errors() {
+        let mut digits = Digit::DIGITS;
+        digits[1] = Digit::default();
+        digits[4] = Digit::default();
+        assert!(matches!(
+            Digits(digits).careful_analysis(),
+            Err(Error::MissingGlyphs { glyphs }) if glyphs == [1, 4],
+        ));
+
+        let mut digits = Digit::DIGITS;
+        digits[3] = Digit::default();
+        assert!(matches!(
+            Digits(digits).careful_analysis(),
+            Err(Error::MissingGlyphs { glyphs }) if glyphs == [3],
+        ));
+
+        let output = [Digit::D1, Digit::default(), Digit::D8, Digit::D8];
+        assert!(matches!(
+            Entry(Digits(Digit::DIGITS), Digits(output)).output_value(),
+            Err(Error::UnmappableOutput { outputs }) if outputs == [1],
+        ));
+    }
+
+    #[test]
+    fn test_generic_decoder_errors() {
+        let alphabet = Alphabet::seven_segment_digits();
+
+        let missing_one = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb | ab ab ab ab";
+        assert!(matches!(
+            alphabet.output_value(missing_one),
+            Err(Error::MissingGlyphs { glyphs }) if glyphs == [1],
+        ));
+
+        let contradictory =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | ab ab ab ab";
+        let contradictory = contradictory.replacen("cagedb", "abcdef", 1);
+        assert!(matches!(
+            alphabet.output_value(&contradictory),
+            Err(Error::NoWiring)
+        ));
+
+        let unmappable = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | ab abc a ab";
+        assert!(matches!(
+            alphabet.output_value(unmappable),
+            Err(Error::UnmappableOutput { outputs }) if outputs == [1, 2],
+        ));
+
+        assert!(matches!(Alphabet::new(0, &[]), Err(Error::NoSegments)));
+        assert!(matches!(
+            Alphabet::new(33, &[]),
+            Err(Error::TooManySegments { n_segments: 33 })
+        ));
+
+        let symmetric = Alphabet::new(2, &["a", "b"]).unwrap();
+        assert!(matches!(
+            symmetric.output_value("a b | a"),
+            Err(Error::AmbiguousWiring { wirings }) if wirings.len() == 2,
+        ));
+        assert!(matches!(
+            symmetric.output_value("a b | ab"),
+            Err(Error::UnmappableOutput { outputs }) if outputs == [0],
+        ));
+
+        // 14! wirings fit, but the search stops at the first two that
+        // disagree
+        let letters: Vec<_> = ('a'..='n').map(String::from).collect();
+        let letters: Vec<_> = letters.iter().map(String::as_str).collect();
+        let singles = Alphabet::new(14, &letters).unwrap();
+        let line = format!("{} | n", letters.join(" "));
+        assert!(matches!(
+            singles.output_value(&line),
+            Err(Error::AmbiguousWiring { wirings }) if wirings.len() == 2,
+        ));
+    }
+
+    #[test]
+    fn test_noisy_decoding() {
+        for line in TEST_INPUT.lines() {
+            let entry: Entry = line.parse().unwrap();
+            let decoded = entry.decode_noisy(0.01).unwrap();
+
+            assert_eq!(0, decoded.flips);
+            assert!(decoded.confidence > 0.95, "{line}");
+            assert!(decoded.readings[0].probability > 0.99, "{line}");
+            assert_eq!(
+                entry.output_value().unwrap() as u64,
+                decoded.readings[0].value
+            );
+        }
+
+        let clean =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf";
+
+        // Three patterns dropped
+        let missing = clean
+            .replace("cdfbe ", "")
+            .replace("fbcad ", "")
+            .replace("cefabd ", "");
+        let decoded = missing
+            .parse::<Entry>()
+            .unwrap()
+            .decode_noisy(0.01)
+            .unwrap();
+        assert_eq!(0, decoded.flips);
+        assert_eq!(5353, decoded.readings[0].value);
+
+        // `dab` (a 7) lost a segment and `eafb` (a 4) gained one
+        let noisy = clean.replace("dab", "da").replace("eafb", "eafbc");
+        let decoded = noisy.parse::<Entry>().unwrap().decode_noisy(0.01).unwrap();
+        assert_eq!(2, decoded.flips);
+        assert_eq!(5353, decoded.readings[0].value);
+    }
+
+    #[test]
+    fn test_noisy_decoding_alternatives() {
+        // With no patterns, the 1 and 7 segments could be anywhere
+        let entry: Entry = "| ab ab abc abc".parse().unwrap();
+        let decoded = entry.decode_noisy(0.01).unwrap();
+
+        assert!(decoded.readings.len() > 1);
+        assert!(decoded.confidence < 0.05);
+        let total: f64 = decoded.readings.iter().map(|r| r.probability).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert_eq!(1177, decoded.readings[0].value);
+
+        let alphabet = Alphabet::new(14, &["a"]).unwrap();
+        assert!(matches!(
+            alphabet.decode_noisy(&[], &[], 0.01),
+            Err(Error::TooManySegments { n_segments: 14 })
+        ));
+    }
+
+    #[test]
+    fn test_noisy_decoding_flip_probability() {
+        let entry: Entry = TEST_INPUT.lines().next().unwrap().parse().unwrap();
+
+        for flip_probability in [0.0, 0.5, 0.9, 1.0, -0.1, f64::NAN] {
+            assert!(
+                matches!(
+                    entry.decode_noisy(flip_probability),
+                    Err(Error::InvalidFlipProbability { .. })
+                ),
+                "{flip_probability}"
+            );
+        }
+        assert!(entry.decode_noisy(0.49).is_ok());
+    }
+
+    #[test]
+    fn test_digit_algebra() {
+        let d: Digit = "abdfg".parse().unwrap();
+        assert_eq!(Digit::D5, d);
+
+        assert_eq!(Digit::D8, Digit::D1 | Digit::D2 | Digit::D6);
+        assert_eq!(Digit::D1, Digit::D1 & Digit::D7);
+        assert_eq!(Digit::D7 - Digit::D1, Digit::D7 ^ Digit::D1);
+        assert_eq!(Digit::D9 + Digit::D2, Digit::D8);
+
+        assert!(Digit::D1.is_subset(&Digit::D7));
+        assert!(!Digit::D7.is_subset(&Digit::D1));
+        assert!(Digit::D8.contains(&Digit::D0));
+        assert_eq!(6, Digit::D9.n_segments());
+        assert_eq!([2, 5], &*Digit::D1.active_segments().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_digit_display() {
+        let rendered = "acf".parse::<Digit>().unwrap().to_string();
+        assert_eq!(
+            " aaaa \n.    c\n.    c\n .... \n.    f\n.    f\n .... \n",
+            rendered
+        );
+        assert_eq!(Digit::D7.to_string(), rendered);
+    }
+
+    #[test]
+    fn test_render_panel() {
+        let entry: Entry =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf"
+                .parse()
+                .unwrap();
+        let intended = entry.intended_output().unwrap();
+
+        assert_eq!([Digit::D5, Digit::D3, Digit::D5, Digit::D3], intended.0,);
+        assert_eq!(
+            concat!(
+                " aaaa    aaaa    aaaa    aaaa \n",
+                "b    .  .    c  b    .  .    c\n",
+                "b    .  .    c  b    .  .    c\n",
+                " dddd    dddd    dddd    dddd \n",
+                ".    f  .    f  .    f  .    f\n",
+                ".    f  .    f  .    f  .    f\n",
+                " gggg    gggg    gggg    gggg \n",
+            ),
+            intended.render().to_string(),
+        );
+
+        // The scrambled output, lit as if it were wired correctly
+        assert_eq!(
+            concat!(
+                " !!!!    aaaa    !!!!    aaaa \n",
+                "b    C  B    c  b    C  B    c\n",
+                "b    C  B    c  b    C  B    c\n",
+                " dddd    dddd    dddd    dddd \n",
+                "E    f  .    f  E    f  .    f\n",
+                "E    f  .    f  E    f  .    f\n",
+                " !!!!    !!!!    !!!!    !!!! \n",
+            ),
+            entry.1.render_against(&intended).to_string(),
+        );
+    }
+
+    fn scramble(alphabet: &Alphabet, wiring: &[u32], glyphs: &[usize]) -> String {
+        let letters = |g: Segments| {
+            let wires = segment_bits(g).map(|s| wiring.iter().position(|&w| w == s).unwrap());
+            wires
+                .map(|w| char::from(b'a' + w as u8))
+                .collect::<String>()
+        };
+        let patterns = alphabet.glyphs.iter().rev().map(|&g| letters(g)).join(" ");
+        let output = glyphs
+            .iter()
+            .map(|&i| letters(alphabet.glyphs[i]))
+            .join(" ");
+        format!("{patterns} | {output}")
+    }
+
+    #[test]
+    fn test_generic_decoder_matches_careful_analysis() {
+        let alphabet = Alphabet::seven_segment_digits();
+        for line in TEST_INPUT.lines() {
+            let entry: Entry = line.parse().unwrap();
+            assert_eq!(
+                entry.output_value().unwrap() as u64,
+                alphabet.output_value(line).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_generic_decoder_hex() {
+        let alphabet = Alphabet::seven_segment_hex();
+        let line = scramble(&alphabet, &[3, 6, 0, 5, 1, 4, 2], &[0xC, 0xA, 0xF, 0xE]);
+
+        assert_eq!(0xCAFE, alphabet.output_value(&line).unwrap());
+    }
+
+    #[test]
+    fn test_generic_decoder_fourteen_segments() {
+        // a-f are the outer segments, g/h the middle halves, i/l the
+        // center verticals and j/k/m/n the diagonals. The last glyph is
+        // an apostrophe, which tells i and l apart.
+        let alphabet = Alphabet::new(
+            14,
+            &[
+                "abcdefkn", "bcj", "abdegh", "abcdh", "bcfgh", "adfgm", "acdefgh", "abc",
+                "abcdefgh", "abcdfgh", "abcefgh", "abcdhil", "adef", "abcdil", "adefg", "aefg",
+                "bcefgh", "ilad", "fjmn", "kmnef", "nj", "kejf", "i",
+            ],
+        )
+        .unwrap();
+        let wiring = [9, 2, 13, 0, 7, 11, 4, 1, 12, 5, 10, 3, 8, 6];
+        let line = scramble(&alphabet, &wiring, &[20, 1, 0, 21, 13, 7]);
+
+        assert_eq!(
+            [20, 1, 0, 21, 13, 7].iter().fold(0, |sum, v| sum * 23 + v),
+            alphabet.output_value(&line).unwrap()
+        );
+
+        let patterns: Vec<_> = line
+            .split('|')
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .map(|w| parse_segments(w, 14).unwrap())
+            .collect();
+        let mut solutions = vec![];
+        alphabet.solve(&patterns, |wiring| {
+            solutions.push(wiring);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(vec![Wiring(wiring.to_vec())], solutions);
+    }
 }
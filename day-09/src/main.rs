@@ -1,8 +1,7 @@
-#![feature(map_first_last)]
-
 use std::{
     cmp::max,
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    env, fmt,
 };
 
 const INPUT: &str = include_str!("../input");
@@ -10,6 +9,37 @@ const INPUT: &str = include_str!("../input");
 fn main() {
     println!("part1: {}", risk_level_sum(INPUT));
     println!("part1: {}", top_three_basin_size_products(INPUT));
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let basins = || {
+            let (board, max_x, max_y) = parse_board(INPUT).unwrap();
+            Basins::label(board, max_x, max_y)
+        };
+
+        match &*arg {
+            "--map" => print!("{}", basins().map()),
+            // Describes the basin around a point, e.g. `--at 3,4`
+            "--at" => {
+                let value = args.next().expect("--at needs a point");
+                let (x, y) = value.split_once(',').expect("--at needs x,y");
+                let c = (x.parse().unwrap(), y.parse().unwrap());
+
+                match basins().basin_at(c) {
+                    Some(basin) => println!(
+                        "basin {}: low point {:?} at height {}, area {}, perimeter {}",
+                        basin.id,
+                        basin.low_point,
+                        basin.low_height,
+                        basin.area(),
+                        basin.perimeter,
+                    ),
+                    None => println!("{c:?} is not in a basin"),
+                }
+            }
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
 }
 
 fn risk_level_sum(s: &str) -> Height {
@@ -28,11 +58,11 @@ fn top_three_basin_size_products(s: &str) -> usize {
 
 fn top_three_basin_size_products_inner(s: &str) -> Result<usize> {
     let (board, max_x, max_y) = parse_board(s)?;
-    let minimums = minimums(&board, max_x, max_y).map(|(c, _)| c);
+    let basins = Basins::label(board, max_x, max_y);
 
-    let mut sizes: Vec<_> = minimums.map(|c| basin_size(&board, c)).collect();
-    sizes.sort_unstable();
-    Ok(sizes.iter().rev().take(3).product())
+    let mut areas: Vec<_> = basins.basins.iter().map(Basin::area).collect();
+    areas.sort_unstable();
+    Ok(areas.iter().rev().take(3).product())
 }
 
 type Coord = (usize, usize);
@@ -66,7 +96,7 @@ fn minimums(
     itertools::iproduct!(0..=max_x, 0..=max_y).filter_map(move |c| {
         let v = board[&c];
         let is_minimum = neighbors(board, c).all(|(_, t)| t > v);
-        is_minimum.then(|| (c, v))
+        is_minimum.then_some((c, v))
     })
 }
 
@@ -82,21 +112,173 @@ fn neighbors(board: &Board, (x, y): Coord) -> impl Iterator<Item = (Coord, Heigh
     itertools::chain!(u, d, l, r)
 }
 
-fn basin_size(board: &Board, start: Coord) -> usize {
-    let mut to_visit = BTreeSet::from_iter([start]);
-    let mut visited = BTreeSet::new();
+type BasinId = usize;
+
+/// Every cell below `MAX_HEIGHT`, grouped into the basins they drain to.
+/// Basins are numbered in order of their low points.
+#[derive(Debug, Clone)]
+struct Basins {
+    board: Board,
+    max_x: usize,
+    max_y: usize,
+    labels: BTreeMap<Coord, BasinId>,
+    /// Indexed by `BasinId`
+    basins: Vec<Basin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Basin {
+    id: BasinId,
+    /// The lowest member; the first one when there's a tie
+    low_point: Coord,
+    low_height: Height,
+    members: BTreeSet<Coord>,
+    /// Member edges that touch a wall, another basin or the edge of the
+    /// board
+    perimeter: usize,
+}
 
-    while let Some(c) = to_visit.pop_first() {
-        visited.insert(c);
+impl Basin {
+    fn area(&self) -> usize {
+        self.members.len()
+    }
+}
 
-        for (nc, nv) in neighbors(board, c) {
-            if nv != MAX_HEIGHT && !visited.contains(&nc) {
-                to_visit.insert(nc);
+impl Basins {
+    /// Each cell drains to its lowest neighbor, as long as that is
+    /// lower still. A flat stretch drains wherever its nearest edge
+    /// does, and one that can't drain anywhere is a basin of its own.
+    fn label(board: Board, max_x: usize, max_y: usize) -> Self {
+        let mut levels = BTreeMap::<_, Vec<_>>::new();
+        for (&c, &height) in &board {
+            if height != MAX_HEIGHT {
+                levels.entry(height).or_default().push(c);
             }
         }
+
+        // Lower cells are labeled first, so there is always a label to
+        // follow downhill
+        let mut labels = BTreeMap::new();
+        let mut n_basins = 0;
+        for (&height, cells) in &levels {
+            let mut to_visit = VecDeque::new();
+            for &c in cells {
+                let downhill = neighbors(&board, c)
+                    .filter(|&(_, nv)| nv < height)
+                    .min_by_key(|&(nc, nv)| (nv, nc));
+                if let Some((nc, _)) = downhill {
+                    labels.insert(c, labels[&nc]);
+                    to_visit.push_back(c);
+                }
+            }
+
+            let mut seeds = cells.iter();
+            loop {
+                while let Some(c) = to_visit.pop_front() {
+                    let id = labels[&c];
+                    for (nc, nv) in neighbors(&board, c) {
+                        if nv == height && !labels.contains_key(&nc) {
+                            labels.insert(nc, id);
+                            to_visit.push_back(nc);
+                        }
+                    }
+                }
+
+                // Whatever is left has no way down
+                match seeds.find(|c| !labels.contains_key(c)) {
+                    Some(&c) => {
+                        labels.insert(c, n_basins);
+                        to_visit.push_back(c);
+                        n_basins += 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let mut members = vec![BTreeSet::new(); n_basins];
+        for (&c, &id) in &labels {
+            members[id].insert(c);
+        }
+
+        let mut lowest: Vec<_> = members
+            .into_iter()
+            .map(|members| {
+                let (low_height, low_point) = members
+                    .iter()
+                    .map(|&c| (board[&c], c))
+                    .min()
+                    .expect("Basin contains its start");
+                (low_point, low_height, members)
+            })
+            .collect();
+        lowest.sort_unstable_by_key(|&(low_point, _, _)| low_point);
+
+        let mut basins = Vec::with_capacity(n_basins);
+        for (id, (low_point, low_height, members)) in lowest.into_iter().enumerate() {
+            for c in &members {
+                labels.insert(*c, id);
+            }
+
+            let perimeter = members
+                .iter()
+                .map(|&c| {
+                    let inside = neighbors(&board, c).filter(|(nc, _)| members.contains(nc));
+                    4 - inside.count()
+                })
+                .sum();
+
+            basins.push(Basin {
+                id,
+                low_point,
+                low_height,
+                members,
+                perimeter,
+            });
+        }
+
+        Self {
+            board,
+            max_x,
+            max_y,
+            labels,
+            basins,
+        }
     }
 
-    visited.len()
+    fn basin_at(&self, c: Coord) -> Option<&Basin> {
+        self.labels.get(&c).map(|&id| &self.basins[id])
+    }
+
+    /// The heights, with each basin in its own color.
+    fn map(&self) -> BasinMap<'_> {
+        BasinMap(self)
+    }
+}
+
+struct BasinMap<'a>(&'a Basins);
+
+impl fmt::Display for BasinMap<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // ANSI background colors, reused once every one has been used
+        const PALETTE: [u8; 6] = [41, 42, 43, 44, 45, 46];
+
+        let basins = self.0;
+        for y in 0..=basins.max_y {
+            for x in 0..=basins.max_x {
+                let height = basins.board[&(x, y)];
+                match basins.labels.get(&(x, y)) {
+                    Some(id) => {
+                        let color = PALETTE[id % PALETTE.len()];
+                        write!(f, "\x1b[{color}m{height}\x1b[0m")?;
+                    }
+                    None => write!(f, "{height}")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
 
 type Error = Box<dyn std::error::Error>;
@@ -117,4 +299,80 @@ mod test {
     fn test_part_2() {
         assert_eq!(1134, top_three_basin_size_products(TEST_INPUT));
     }
+
+    fn test_basins() -> Basins {
+        let (board, max_x, max_y) = parse_board(TEST_INPUT).unwrap();
+        Basins::label(board, max_x, max_y)
+    }
+
+    #[test]
+    fn test_labeling() {
+        let basins = test_basins();
+        assert_eq!(4, basins.basins.len());
+
+        let top_left = basins.basin_at((0, 0)).unwrap();
+        assert_eq!(0, top_left.id);
+        assert_eq!((1, 0), top_left.low_point);
+        assert_eq!(1, top_left.low_height);
+        assert_eq!(3, top_left.area());
+        assert_eq!(8, top_left.perimeter);
+        assert_eq!(
+            BTreeSet::from_iter([(0, 0), (1, 0), (0, 1)]),
+            top_left.members
+        );
+
+        let top_right = basins.basin_at((9, 0)).unwrap();
+        assert_eq!((9, 0), top_right.low_point);
+        assert_eq!(9, top_right.area());
+
+        assert_eq!(None, basins.basin_at((2, 0)));
+
+        // Every low point is in its own basin
+        let (board, max_x, max_y) = parse_board(TEST_INPUT).unwrap();
+        let low_points: BTreeSet<_> = minimums(&board, max_x, max_y).map(|(c, _)| c).collect();
+        let basin_lows: BTreeSet<_> = basins.basins.iter().map(|b| b.low_point).collect();
+        assert_eq!(low_points, basin_lows);
+    }
+
+    #[test]
+    fn test_labeling_without_walls() {
+        // Both ends are low points, and the middle drains downhill
+        let (board, max_x, max_y) = parse_board("1231\n9999\n").unwrap();
+        let basins = Basins::label(board, max_x, max_y);
+
+        assert_eq!(2, basins.basins.len());
+        let left = basins.basin_at((1, 0)).unwrap();
+        assert_eq!((0, 0), left.low_point);
+        assert_eq!(BTreeSet::from_iter([(0, 0), (1, 0)]), left.members);
+        let right = basins.basin_at((2, 0)).unwrap();
+        assert_eq!((3, 0), right.low_point);
+        assert_eq!(BTreeSet::from_iter([(2, 0), (3, 0)]), right.members);
+        assert_eq!(6, right.perimeter);
+
+        // A flat stretch drains to its nearest way down, and one with no
+        // way down is a basin of its own
+        let (board, max_x, max_y) = parse_board("155559\n999999\n977999\n").unwrap();
+        let basins = Basins::label(board, max_x, max_y);
+
+        assert_eq!(2, basins.basins.len());
+        assert_eq!(5, basins.basin_at((4, 0)).unwrap().area());
+        let flat = basins.basin_at((2, 2)).unwrap();
+        assert_eq!((1, 2), flat.low_point);
+        assert_eq!(2, flat.area());
+    }
+
+    #[test]
+    fn test_map() {
+        let map = test_basins().map().to_string();
+
+        assert!(map.starts_with("\x1b[41m2\x1b[0m\x1b[41m1\x1b[0m9"));
+
+        let plain = map.replace("\x1b[0m", "");
+        let plain = (41..=46).fold(plain, |p, c| p.replace(&format!("\x1b[{c}m"), ""));
+        let expected: String = TEST_INPUT
+            .lines()
+            .map(|l| format!("{}\n", l.trim()))
+            .collect();
+        assert_eq!(expected, plain);
+    }
 }